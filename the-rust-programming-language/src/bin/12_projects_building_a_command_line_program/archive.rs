@@ -0,0 +1,175 @@
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD];
+const XZ_MAGIC: &[u8] = &[0xFD, b'7', b'z', b'X', b'Z', 0x00];
+/// 本地文件头，空的 zip 文件只有结尾的目录记录
+const ZIP_MAGIC: [&[u8]; 2] = [b"PK\x03\x04", b"PK\x05\x06"];
+/// POSIX tar 的文件头在偏移 257 的地方有 `ustar`
+const TAR_MAGIC: &[u8] = b"ustar";
+const TAR_MAGIC_OFFSET: usize = 257;
+
+/// 根据开头的魔数判断是不是 gzip/zstd/xz 压缩的内容，是的话返回解压之后的内容，否则原样返回
+///
+/// 和文件的扩展名无关，`app.log.1` 只要是 gzip 格式的也会被解压
+pub fn decompress<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    let head = reader.fill_buf()?;
+    Ok(if head.starts_with(GZIP_MAGIC) {
+        // 日志轮转时可能把多个 gzip 流拼在一起
+        Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader)))
+    } else if head.starts_with(ZSTD_MAGIC) {
+        let decoder = ruzstd::StreamingDecoder::new(reader).map_err(io::Error::other)?;
+        Box::new(BufReader::new(decoder))
+    } else if head.starts_with(XZ_MAGIC) {
+        // lzma-rs 没有流式的 xz 解码器，只能一次解压到内存里
+        let mut decompressed = Vec::new();
+        lzma_rs::xz_decompress(&mut reader, &mut decompressed).map_err(io::Error::other)?;
+        Box::new(Cursor::new(decompressed))
+    } else {
+        Box::new(reader)
+    })
+}
+
+/// 是不是压缩过的内容或者归档
+pub fn is_container(head: &[u8]) -> bool {
+    [GZIP_MAGIC, ZSTD_MAGIC, XZ_MAGIC]
+        .iter()
+        .any(|magic| head.starts_with(magic))
+        || Archive::detect(head).is_some()
+}
+
+/// 支持的归档格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Archive {
+    Tar,
+    Zip,
+}
+
+impl Archive {
+    /// 根据(解压之后的)开头内容判断归档格式
+    pub fn detect(head: &[u8]) -> Option<Self> {
+        if ZIP_MAGIC.iter().any(|magic| head.starts_with(magic)) {
+            Some(Archive::Zip)
+        } else if head.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()) == Some(TAR_MAGIC)
+        {
+            Some(Archive::Tar)
+        } else {
+            None
+        }
+    }
+
+    /// 按顺序把归档中的每个普通文件交给 `f`，第一个参数是文件在归档中的路径
+    ///
+    /// 归档中被压缩的文件由调用方再解压，嵌套的归档不会继续展开
+    pub fn for_each<R: Read>(
+        self,
+        mut reader: R,
+        mut f: impl FnMut(&str, &mut dyn BufRead) -> io::Result<()>,
+    ) -> io::Result<()> {
+        match self {
+            Archive::Tar => {
+                let mut archive = tar::Archive::new(reader);
+                for entry in archive.entries()? {
+                    let entry = entry?;
+                    if !entry.header().entry_type().is_file() {
+                        continue;
+                    }
+                    let path = entry.path()?.to_string_lossy().into_owned();
+                    f(&path, &mut BufReader::new(entry))?;
+                }
+            }
+            Archive::Zip => {
+                // zip 的目录在文件末尾，需要随机读取，标准输入或者解压出来的内容只能先读到内存里
+                let mut data = Vec::new();
+                reader.read_to_end(&mut data)?;
+                let mut archive =
+                    zip::ZipArchive::new(Cursor::new(data)).map_err(io::Error::other)?;
+                for i in 0..archive.len() {
+                    let mut file = archive.by_index(i).map_err(io::Error::other)?;
+                    if !file.is_file() {
+                        continue;
+                    }
+                    let path = file.name().to_string();
+                    f(&path, &mut BufReader::new(&mut file))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::path::{Path, PathBuf};
+
+    /// 测试用的文件都是由 poem.txt 生成的:
+    ///
+    /// ```bash
+    /// gzip -9n < poem.txt > poem.txt.gz
+    /// zstd -19 < poem.txt > poem.txt.zst
+    /// xz -9 < poem.txt > poem.txt.xz
+    /// # frogs/well.txt.gz 是压缩过的另一首诗
+    /// tar --mtime=@0 --owner=0 --group=0 --numeric-owner --sort=name -cf poems.tar poem.txt frogs
+    /// gzip -9n < poems.tar > poems.tar.gz
+    /// zip -X -r poems.zip poem.txt frogs
+    /// ```
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/bin/12_projects_building_a_command_line_program/fixtures")
+            .join(name)
+    }
+
+    fn read(name: &str) -> String {
+        let file = BufReader::new(File::open(fixture(name)).unwrap());
+        let mut contents = String::new();
+        decompress(file)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    fn entries(name: &str) -> Vec<(String, String)> {
+        let mut reader = decompress(BufReader::new(File::open(fixture(name)).unwrap())).unwrap();
+        let archive = Archive::detect(reader.fill_buf().unwrap()).unwrap();
+        let mut entries = Vec::new();
+        archive
+            .for_each(reader, |path, entry| {
+                let mut contents = String::new();
+                decompress(entry)?.read_to_string(&mut contents)?;
+                entries.push((path.to_string(), contents));
+                Ok(())
+            })
+            .unwrap();
+        entries
+    }
+
+    #[test]
+    fn decompress_by_magic_bytes() {
+        let poem_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("poem.txt");
+        let poem = std::fs::read_to_string(&poem_path).unwrap();
+        assert_eq!(poem, read("poem.txt.gz"));
+        assert_eq!(poem, read("poem.txt.zst"));
+        assert_eq!(poem, read("poem.txt.xz"));
+        // 不是压缩格式的内容原样返回
+        assert_eq!(poem, read(poem_path.to_str().unwrap()));
+    }
+
+    #[test]
+    fn archive_entries() {
+        let tar = entries("poems.tar");
+        assert_eq!(
+            vec!["poem.txt", "frogs/well.txt.gz"],
+            tar.iter()
+                .map(|(path, _)| path.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert!(tar[0].1.starts_with("I'm noBODY! Who are you?\n"));
+        assert_eq!("A frog in a well\ncannot talk about the sea.\n", tar[1].1);
+        assert_eq!(tar, entries("poems.tar.gz"));
+        assert_eq!(tar, entries("poems.zip"));
+        assert_eq!(None, Archive::detect(b"I'm noBODY!"));
+    }
+}
@@ -0,0 +1,242 @@
+use crate::glob::GlobError;
+use crate::regex::RegexError;
+use crate::settings::SettingsError;
+
+pub const USAGE: &str = "\
+Usage: minigrep [OPTIONS] <QUERY> <PATH>...
+       minigrep [OPTIONS] -e <QUERY>... <PATH>...
+       minigrep index build [--include <GLOB>] [--exclude <GLOB>] [--no-ignore] [--hidden] <DIR>...
+
+PATH can be a file, a directory, or `-` for standard input.
+`index build` writes a trigram index to DIR/.minigrep-index for --indexed searches.
+
+Defaults come from ~/.minigreprc (or $MINIGREP_CONFIG) and the nearest .minigreprc above the
+current directory, in TOML or JSON: ignore-case, line-number, hidden, no-ignore, fold, color,
+threads, [types] and [colors]. CASE_INSENSITIVE, MINIGREP_COLOR and MINIGREP_THREADS override
+them, and command line options override everything: -s, --no-line-number, --no-hidden and
+--ignore turn off a switch that a config file or the environment turned on.
+
+Options:
+  -i, --ignore-case         case insensitive search (also enabled by CASE_INSENSITIVE env var)
+  -s, --case-sensitive      case sensitive search, overrides -i
+      --fold <MODE>         Unicode case folding for -i: full (default, ß = ss) or simple
+  -v, --invert-match        select non-matching lines
+  -n, --line-number         print line number with output lines
+      --no-line-number      don't print line numbers, overrides -n
+  -c, --count               print only a count of selected lines per file
+  -l, --files-with-matches  print only names of files with selected lines
+  -w, --word-regexp         match only whole words
+  -E, --regex               treat QUERY as a regular expression
+  -e, --regexp <QUERY>      search for QUERY; repeat to match any of several queries
+  -A, --after-context <NUM> print NUM lines of trailing context
+  -B, --before-context <NUM>
+                            print NUM lines of leading context
+  -C, --context <NUM>       print NUM lines of output context
+  -j, --threads <NUM>       search NUM files in parallel (default: number of CPUs)
+      --color <WHEN>        highlight matches, paths and line numbers: auto, always or never
+      --json                print one JSON object per selected line: path, line, columns, text
+                            (columns are 1-based character ranges, end exclusive)
+      --replace <TEXT>      print a unified diff that replaces every match with TEXT;
+                            with -E, $1 or ${1} refers to a capture group and $$ is a literal $
+      --in-place            with --replace, rewrite the files instead of printing a diff
+      --archives            also search the files inside tar and zip archives;
+                            gzip, zstd and xz input is always decompressed
+      --indexed             skip files that the index of a searched directory rules out;
+                            files changed since the index was built are always searched
+  -t, --type <TYPE>         search only files of TYPE, as defined under [types] in .minigreprc
+  -T, --type-not <TYPE>     skip files of TYPE
+      --include <GLOB>      search only files that match GLOB
+      --exclude <GLOB>      skip files and directories that match GLOB
+      --no-ignore           don't respect .gitignore, .ignore and the global git ignore file
+      --ignore              respect the ignore files, overrides --no-ignore
+      --hidden              search hidden files and directories
+      --no-hidden           skip hidden files and directories, overrides --hidden
+      --debug-config        print the resolved settings and where each one comes from
+  -h, --help                print this help
+      --                    treat all following arguments as positional";
+
+/// 命令行参数的使用错误
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum ArgsError {
+    /// `-h/--help` 不算真正的错误，交给 main 打印帮助信息
+    #[error("help requested")]
+    Help,
+    /// `--debug-config` 也不是错误，里面是要输出的设置
+    #[error("debug config requested")]
+    DebugConfig(String),
+    #[error("unknown flag '{0}'")]
+    UnknownFlag(String),
+    #[error("flag '{0}' requires a value")]
+    MissingValue(String),
+    #[error("flag '{0}' doesn't take a value")]
+    UnexpectedValue(String),
+    #[error("flag '{0}' expects a number, got '{1}'")]
+    InvalidNumber(String, String),
+    #[error("invalid value '{1}' for '{0}'")]
+    InvalidChoice(String, String),
+    #[error("'{0}' requires '{1}'")]
+    Requires(String, String),
+    #[error("'{0}' can't be used with '{1}'")]
+    Conflicts(String, String),
+    #[error("didn't get a query string")]
+    MissingQuery,
+    #[error("didn't get a file or directory to search")]
+    MissingPath,
+    #[error("not a directory: {0}")]
+    NotADirectory(String),
+    #[error("no such file or directory: {0}")]
+    PathNotFound(String),
+    #[error(transparent)]
+    InvalidGlob(#[from] GlobError),
+    #[error("unknown file type '{0}', define it under [types] in .minigreprc")]
+    UnknownType(String),
+    #[error(transparent)]
+    InvalidConfig(#[from] SettingsError),
+    #[error("invalid regular expression: {0}")]
+    InvalidRegex(#[from] RegexError),
+}
+
+/// 解析出来的单个参数
+#[derive(Debug, PartialEq)]
+pub enum Arg {
+    /// `-i`，多个短选项可以合在一起写，例如 `-in`
+    Short(char),
+    /// `--ignore-case`，也可以写成 `--include=*.rs` 的形式
+    Long(String),
+    /// 位置参数，`--` 之后的所有参数都是位置参数
+    Value(String),
+}
+
+/// 一个很小的命令行词法分析器，只负责把参数拆成 `Arg`，具体含义由调用方决定
+pub struct Parser<I: Iterator<Item = String>> {
+    args: I,
+    /// `-in` 这种合写的短选项中还没处理的部分
+    shorts: Vec<char>,
+    /// `--include=*.rs` 中的选项名以及 `=` 后面的值
+    long_value: Option<(String, String)>,
+    /// 遇到 `--` 之后就不再解析选项了
+    only_values: bool,
+}
+
+impl<I: Iterator<Item = String>> Parser<I> {
+    pub fn new(args: I) -> Self {
+        Parser {
+            args,
+            shorts: Vec::new(),
+            long_value: None,
+            only_values: false,
+        }
+    }
+
+    pub fn next(&mut self) -> Result<Option<Arg>, ArgsError> {
+        if let Some((name, _)) = self.long_value.take() {
+            return Err(ArgsError::UnexpectedValue(format!("--{}", name)));
+        }
+        if !self.shorts.is_empty() {
+            return Ok(Some(Arg::Short(self.shorts.remove(0))));
+        }
+        let Some(arg) = self.args.next() else {
+            return Ok(None);
+        };
+        if self.only_values {
+            return Ok(Some(Arg::Value(arg)));
+        }
+        if arg == "--" {
+            self.only_values = true;
+            return self.next();
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            return Ok(Some(match long.split_once('=') {
+                Some((name, value)) => {
+                    self.long_value = Some((name.to_string(), value.to_string()));
+                    Arg::Long(name.to_string())
+                }
+                None => Arg::Long(long.to_string()),
+            }));
+        }
+        // 单独的 `-` 通常表示标准输入，当作位置参数
+        match arg.strip_prefix('-') {
+            Some(shorts) if !shorts.is_empty() => {
+                self.shorts = shorts.chars().collect();
+                self.next()
+            }
+            _ => Ok(Some(Arg::Value(arg))),
+        }
+    }
+
+    /// 取出上一个选项的值: `--include=*.rs`、`-A3` 或者下一个参数
+    pub fn value(&mut self, flag: &str) -> Result<String, ArgsError> {
+        if let Some((_, value)) = self.long_value.take() {
+            return Ok(value);
+        }
+        if !self.shorts.is_empty() {
+            return Ok(self.shorts.drain(..).collect());
+        }
+        self.args
+            .next()
+            .ok_or_else(|| ArgsError::MissingValue(flag.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Vec<Arg>, ArgsError> {
+        let mut parser = Parser::new(args.iter().map(|arg| arg.to_string()));
+        let mut parsed = Vec::new();
+        while let Some(arg) = parser.next()? {
+            if arg == Arg::Long("include".to_string()) {
+                parsed.push(Arg::Value(parser.value("--include")?));
+            } else {
+                parsed.push(arg);
+            }
+        }
+        Ok(parsed)
+    }
+
+    #[test]
+    fn shorts_longs_and_values() {
+        assert_eq!(
+            Ok(vec![
+                Arg::Short('i'),
+                Arg::Short('n'),
+                Arg::Long("count".to_string()),
+                Arg::Value("body".to_string()),
+                Arg::Value("-".to_string()),
+            ]),
+            parse(&["-in", "--count", "body", "-"])
+        );
+    }
+
+    #[test]
+    fn option_values() {
+        assert_eq!(
+            Ok(vec![
+                Arg::Value("*.rs".to_string()),
+                Arg::Value("*.md".to_string())
+            ]),
+            parse(&["--include=*.rs", "--include", "*.md"])
+        );
+        assert_eq!(
+            Err(ArgsError::MissingValue("--include".to_string())),
+            parse(&["--include"])
+        );
+        assert_eq!(
+            Err(ArgsError::UnexpectedValue("--count".to_string())),
+            parse(&["--count=3"])
+        );
+    }
+
+    #[test]
+    fn double_dash() {
+        assert_eq!(
+            Ok(vec![
+                Arg::Short('v'),
+                Arg::Value("-v".to_string()),
+                Arg::Value("--help".to_string()),
+            ]),
+            parse(&["-v", "--", "-v", "--help"])
+        );
+    }
+}
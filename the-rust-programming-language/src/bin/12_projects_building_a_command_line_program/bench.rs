@@ -0,0 +1,137 @@
+mod fold;
+mod matcher;
+mod regex;
+
+use crate::fold::Folding;
+use crate::matcher::{MatchOptions, Matcher};
+use std::time::{Duration, Instant};
+
+/// 性能比较：逐行 contains VS 编译好的 Matcher
+///
+/// cargo r --release --bin minigrep-bench
+///
+/// ## 目录
+/// - 原来的实现对每一行调用 `str::contains`，忽略大小写时还要先 `to_lowercase` 分配一个新字符串
+/// - `Matcher` 在搜索之前只构建一次:
+///   - 单个字面量用 Boyer-Moore-Horspool，查询越长跳得越远；单个字节用逐字(word)扫描的 memchr
+///   - 多个字面量用 Aho-Corasick，一遍扫描同时找所有模式，模式越多优势越明显；
+///     只有两三个模式时，标准库用 SIMD 实现的 `contains` 各扫一遍反而更快
+///   - 忽略大小写时边折叠边比较，不分配内存
+/// - 一定要用 --release 运行，debug 模式下的结果没有参考价值
+///
+fn main() {
+    let corpus = corpus();
+    let lines: Vec<&str> = corpus.lines().collect();
+    println!(
+        "{} lines, {} bytes, best of {} runs\n",
+        lines.len(),
+        corpus.len(),
+        RUNS
+    );
+    println!(
+        "{:<28}{:>12}{:>12}{:>9}",
+        "case", "contains", "Matcher", "speedup"
+    );
+
+    let case_sensitive = MatchOptions {
+        case_sensitive: true,
+        ..Default::default()
+    };
+    for query in ["somebody", "the livelong day", "!"] {
+        bench(
+            &lines,
+            &format!("literal {:?}", query),
+            |line| line.contains(query),
+            &compile(&[query], case_sensitive),
+        );
+    }
+
+    let patterns = [
+        "frog",
+        "bog",
+        "banish",
+        "admiring",
+        "public",
+        "dreary",
+        "tell",
+        "name",
+        "pair",
+        "know",
+        "somebody",
+        "livelong",
+        "抽象",
+        "开销",
+        "零成本",
+        "运行时",
+    ];
+    for n in [2, 5, 16] {
+        let patterns = &patterns[..n];
+        bench(
+            &lines,
+            &format!("{} literals (-e)", n),
+            |line| patterns.iter().any(|pattern| line.contains(pattern)),
+            &compile(patterns, case_sensitive),
+        );
+    }
+
+    let ignore_case = MatchOptions {
+        folding: Folding::Simple,
+        ..Default::default()
+    };
+    for query in ["NOBODY", "零成本"] {
+        let lower = query.to_lowercase();
+        bench(
+            &lines,
+            &format!("ignore case {:?}", query),
+            |line| line.to_lowercase().contains(&lower),
+            &compile(&[query], ignore_case),
+        );
+    }
+}
+
+const RUNS: usize = 5;
+
+fn compile(patterns: &[&str], options: MatchOptions) -> Matcher {
+    let patterns: Vec<String> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+    Matcher::new(&patterns, options).unwrap()
+}
+
+/// 测试用的文本: poem.txt 加上一行中文，重复很多次
+fn corpus() -> String {
+    // 不依赖运行时的当前目录，读不到时直接失败，否则测出来的只有中文那一行
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("poem.txt");
+    let poem = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e));
+    let chinese = "迭代器是 Rust 的零成本抽象之一，它意味着抽象并不会引入运行时开销\n";
+    format!("{}{}", poem, chinese).repeat(50_000)
+}
+
+/// 分别统计匹配的行数，两种实现的结果必须一样
+fn bench(lines: &[&str], name: &str, naive: impl Fn(&str) -> bool, matcher: &Matcher) {
+    let (expected, naive) = best_of(|| lines.iter().filter(|line| naive(line)).count());
+    let (found, compiled) = best_of(|| {
+        lines
+            .iter()
+            .filter(|line| matcher.find_at(line, 0).is_some())
+            .count()
+    });
+    assert_eq!(expected, found, "{}", name);
+    println!(
+        "{:<28}{:>10.2?}{:>12.2?}{:>8.1}x",
+        name,
+        naive,
+        compiled,
+        naive.as_secs_f64() / compiled.as_secs_f64()
+    );
+}
+
+fn best_of(f: impl Fn() -> usize) -> (usize, Duration) {
+    let mut best = Duration::MAX;
+    let mut result = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        result = f();
+        best = best.min(start.elapsed());
+    }
+    (result, best)
+}
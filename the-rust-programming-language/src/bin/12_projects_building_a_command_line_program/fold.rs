@@ -0,0 +1,207 @@
+use std::ops::Range;
+
+/// 大小写折叠(case folding)的方式，参考 Unicode 的 CaseFolding.txt
+///
+/// - `Simple`: 一个字符只折叠成一个字符，例如 `ẞ` 和 `ß` 相等，但 `ß` 和 `ss` 不相等
+/// - `Full`: 一个字符可以折叠成多个字符，例如 `ß` 折叠成 `ss`，`İ` 折叠成 `i̇`
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Folding {
+    Simple,
+    #[default]
+    Full,
+}
+
+/// 一个字符折叠之后的结果，最多 3 个字符，放在栈上，不需要分配内存
+pub struct Folded {
+    chars: [char; 3],
+    len: usize,
+    pos: usize,
+}
+
+impl Iterator for Folded {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if self.pos == self.len {
+            return None;
+        }
+        self.pos += 1;
+        Some(self.chars[self.pos - 1])
+    }
+}
+
+/// 标准库没有提供 case folding，这里用大小写转换近似:
+/// - simple: `ς` -> `Σ` -> `σ`，`ſ` -> `S` -> `s`，结果不是一个字符时退回 `to_lowercase`
+/// - full: 先转小写再转大写再转小写，`ẞ` -> `ß` -> `SS` -> `ss`
+pub fn fold(c: char, folding: Folding) -> Folded {
+    let mut folded = Folded {
+        chars: [c; 3],
+        len: 1,
+        pos: 0,
+    };
+    // ASCII 是最常见的情况，直接处理
+    if c.is_ascii() {
+        folded.chars[0] = c.to_ascii_lowercase();
+        return folded;
+    }
+    // 土耳其语的无点 `ı` 在 CaseFolding.txt 里不折叠成 `i`；
+    // 中日韩文字、假名、谚文等都没有大小写，也不用去查大小写转换的表
+    if c == 'ı' || ('\u{2D30}'..'\u{A640}').contains(&c) || ('\u{ABC0}'..'\u{FB00}').contains(&c) {
+        return folded;
+    }
+    match folding {
+        Folding::Simple => {
+            folded.chars[0] = single(c.to_uppercase())
+                .and_then(|upper| single(upper.to_lowercase()))
+                .or_else(|| single(c.to_lowercase()))
+                .unwrap_or(c);
+        }
+        Folding::Full => {
+            folded.len = 0;
+            for (i, lower) in c
+                .to_lowercase()
+                .flat_map(char::to_uppercase)
+                .flat_map(char::to_lowercase)
+                .take(3)
+                .enumerate()
+            {
+                folded.chars[i] = lower;
+                folded.len = i + 1;
+            }
+        }
+    }
+    folded
+}
+
+/// 迭代器中只有一个字符时返回这个字符
+fn single(mut chars: impl Iterator<Item = char>) -> Option<char> {
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+/// 忽略大小写的子串搜索
+///
+/// 不像 `line.to_lowercase().contains(query)` 那样为每一行分配新的字符串，
+/// 而是边折叠边比较，所以返回的字节范围在原来的行中也是有效的。
+/// 匹配的开头和结尾都必须落在原来的字符边界上，例如 `s` 不会匹配 `ß` 的一半
+#[derive(Debug)]
+pub struct CaseInsensitive {
+    /// 折叠之后的查询字符串
+    query: Vec<char>,
+    folding: Folding,
+    /// 匹配可能从哪些字节开始: 折叠后和查询第一个字符相同的 ASCII 字节，以及所有多字节字符的首字节
+    first_bytes: Box<[bool; 256]>,
+}
+
+impl CaseInsensitive {
+    pub fn new(query: &str, folding: Folding) -> Self {
+        let query: Vec<char> = query.chars().flat_map(|c| fold(c, folding)).collect();
+        let mut first_bytes = Box::new([false; 256]);
+        for (b, first) in first_bytes.iter_mut().enumerate() {
+            *first = match b as u8 {
+                b if b.is_ascii() => query.first() == Some(&(b.to_ascii_lowercase() as char)),
+                // UTF-8 的后续字节不是字符的开头
+                b => b >= 0xC0,
+            };
+        }
+        CaseInsensitive {
+            query,
+            folding,
+            first_bytes,
+        }
+    }
+
+    /// 从 `start` 开始查找第一个匹配
+    ///
+    /// 先用 `first_bytes` 快速跳过不可能是开头的字节，只在剩下的位置完整地匹配一遍
+    pub fn find_at(&self, haystack: &str, start: usize) -> Option<Range<usize>> {
+        if self.query.is_empty() {
+            return Some(start..start);
+        }
+        let mut at = start;
+        while let Some(i) = haystack.as_bytes()[at..]
+            .iter()
+            .position(|&b| self.first_bytes[b as usize])
+        {
+            at += i;
+            if let Some(end) = self.match_at(haystack, at) {
+                return Some(at..end);
+            }
+            at += 1;
+        }
+        None
+    }
+
+    /// 从 `at` 开始是否匹配，匹配时返回结束的位置
+    fn match_at(&self, haystack: &str, at: usize) -> Option<usize> {
+        let bytes = haystack.as_bytes();
+        let mut query = self.query.iter();
+        let mut end = at;
+        while query.len() > 0 {
+            let &b = bytes.get(end)?;
+            // ASCII 不需要解码，直接转小写比较
+            if b.is_ascii() {
+                if query.next() != Some(&(b.to_ascii_lowercase() as char)) {
+                    return None;
+                }
+                end += 1;
+                continue;
+            }
+            let c = haystack[end..].chars().next()?;
+            for folded in fold(c, self.folding) {
+                if query.next() != Some(&folded) {
+                    return None;
+                }
+            }
+            end += c.len_utf8();
+        }
+        Some(end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+
+    fn find_all(query: &str, haystack: &str, folding: Folding) -> Vec<Range<usize>> {
+        Matcher::CaseInsensitive(CaseInsensitive::new(query, folding))
+            .find_iter(haystack)
+            .collect()
+    }
+
+    #[test]
+    fn simple_and_full_folding() {
+        assert_eq!(vec![0..7], find_all("STRAẞE", "straße", Folding::Simple));
+        assert!(find_all("STRASSE", "straße", Folding::Simple).is_empty());
+        assert_eq!(vec![0..7], find_all("STRASSE", "straße", Folding::Full));
+        assert_eq!(vec![0..8], find_all("strasse", "STRAẞE", Folding::Full));
+        // `s` 只能匹配 `ß` 的一半，不算匹配
+        assert_eq!(vec![0..1], find_all("s", "sß", Folding::Full));
+        assert_eq!(vec![0..14], find_all("ΣΊΣΥΦΟΣ", "σίσυφος", Folding::Full));
+    }
+
+    #[test]
+    fn offsets_in_original_line() {
+        // `İ` 小写之后是 3 个字节，用 to_lowercase 的位置会错开
+        let line = "İstanbul ist";
+        assert_eq!(vec![10..13], find_all("ist", line, Folding::Full));
+        assert_eq!(vec![0..9], find_all("İSTANBUL", line, Folding::Simple));
+        assert!(find_all("ı", line, Folding::Full).is_empty());
+    }
+
+    #[test]
+    fn chinese_text() {
+        let line = "测试remove方法，Rust pop 中文!";
+        let found = find_all("REMOVE方法", line, Folding::Full);
+        assert_eq!(vec![6..18], found);
+        assert_eq!("remove方法", &line[found[0].clone()]);
+        assert_eq!(vec![21..25], find_all("rUsT", line, Folding::Simple));
+        assert_eq!(
+            "中文",
+            &line[find_all("中文", line, Folding::Full)[0].clone()]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+/// 简单的 glob 匹配
+///
+/// - `*` 匹配除 `/` 以外的任意多个字符
+/// - `?` 匹配除 `/` 以外的单个字符
+/// - `**` 匹配任意多层目录，例如 `**/tests/*.rs`、`src/**`
+/// - `[abc]` `[a-z]` `[!a-z]` 字符类
+/// - 以 `/` 结尾的模式只匹配目录，例如 `target/`
+/// - 模式中间或开头含有 `/` 时，按相对路径匹配，否则只匹配文件名(任意层级)
+#[derive(Debug)]
+pub struct Glob {
+    tokens: Vec<Token>,
+    dir_only: bool,
+    /// 是否按完整的相对路径匹配
+    anchored: bool,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum GlobError {
+    #[error("unclosed character class in glob `{0}`")]
+    UnclosedClass(String),
+    #[error("empty glob pattern")]
+    Empty,
+}
+
+#[derive(Debug)]
+enum Token {
+    Char(char),
+    /// `?`
+    Any,
+    /// `*`
+    Star,
+    /// `**`，可以跨越 `/`
+    AnyPath,
+    /// `**/`，匹配零个或多个目录
+    AnyDirs,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self, GlobError> {
+        let (pattern, dir_only) = match pattern.strip_suffix('/') {
+            Some(pattern) => (pattern, true),
+            None => (pattern, false),
+        };
+        if pattern.is_empty() {
+            return Err(GlobError::Empty);
+        }
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let token = match chars[i] {
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    i += 1;
+                    if chars.get(i + 1) == Some(&'/') {
+                        i += 1;
+                        Token::AnyDirs
+                    } else {
+                        Token::AnyPath
+                    }
+                }
+                '*' => Token::Star,
+                '?' => Token::Any,
+                '[' => {
+                    let (token, end) = Self::parse_class(&chars, i)
+                        .ok_or_else(|| GlobError::UnclosedClass(pattern.to_string()))?;
+                    i = end;
+                    token
+                }
+                '\\' if i + 1 < chars.len() => {
+                    i += 1;
+                    Token::Char(chars[i])
+                }
+                c => Token::Char(c),
+            };
+            tokens.push(token);
+            i += 1;
+        }
+        Ok(Glob {
+            tokens,
+            dir_only,
+            anchored,
+        })
+    }
+
+    /// 返回字符类对应的 token，以及 `]` 所在的下标
+    fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+        let mut i = start + 1;
+        let negated = matches!(chars.get(i), Some('!' | '^'));
+        if negated {
+            i += 1;
+        }
+        let mut ranges = Vec::new();
+        // 紧跟在 `[` 后面的 `]` 是普通字符
+        let mut first = true;
+        loop {
+            let c = *chars.get(i)?;
+            if c == ']' && !first {
+                return Some((Token::Class { negated, ranges }, i));
+            }
+            first = false;
+            if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&c| c != ']') {
+                ranges.push((c, chars[i + 2]));
+                i += 3;
+            } else {
+                ranges.push((c, c));
+                i += 1;
+            }
+        }
+    }
+
+    /// `path` 是以 `/` 分隔的相对路径
+    pub fn is_match(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let target = if self.anchored {
+            path
+        } else {
+            path.rsplit('/').next().unwrap_or(path)
+        };
+        let chars: Vec<char> = target.chars().collect();
+        Self::match_tokens(&self.tokens, &chars)
+    }
+
+    fn match_tokens(tokens: &[Token], text: &[char]) -> bool {
+        let Some((token, rest)) = tokens.split_first() else {
+            return text.is_empty();
+        };
+        match token {
+            Token::Star => (0..=text.len())
+                .take_while(|&n| n == 0 || text[n - 1] != '/')
+                .any(|n| Self::match_tokens(rest, &text[n..])),
+            Token::AnyPath => (0..=text.len()).any(|n| Self::match_tokens(rest, &text[n..])),
+            // 只能在目录边界上跳过
+            Token::AnyDirs => {
+                Self::match_tokens(rest, text)
+                    || (0..text.len())
+                        .filter(|&n| text[n] == '/')
+                        .any(|n| Self::match_tokens(rest, &text[n + 1..]))
+            }
+            Token::Any => {
+                matches!(text.first(), Some(&c) if c != '/') && Self::match_tokens(rest, &text[1..])
+            }
+            Token::Char(expected) => {
+                text.first() == Some(expected) && Self::match_tokens(rest, &text[1..])
+            }
+            Token::Class { negated, ranges } => match text.first() {
+                Some(&c) if c != '/' => {
+                    let found = ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+                    found != *negated && Self::match_tokens(rest, &text[1..])
+                }
+                _ => false,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, path: &str, is_dir: bool) -> bool {
+        Glob::new(pattern).unwrap().is_match(path, is_dir)
+    }
+
+    #[test]
+    fn file_name_patterns() {
+        assert!(is_match("*.rs", "src/bin/minigrep.rs", false));
+        assert!(is_match("lib_?rate.rs", "lib_crate.rs", false));
+        assert!(is_match("[a-c]*.txt", "bar.txt", false));
+        assert!(!is_match("[!a-c]*.txt", "bar.txt", false));
+        assert!(!is_match("*.rs", "poem.txt", false));
+    }
+
+    #[test]
+    fn path_patterns() {
+        assert!(is_match("src/*.rs", "src/main.rs", false));
+        assert!(!is_match("src/*.rs", "src/bin/minigrep.rs", false));
+        assert!(is_match("src/**/*.rs", "src/main.rs", false));
+        assert!(is_match("src/**/*.rs", "src/bin/12/minigrep.rs", false));
+        assert!(is_match("**/tests", "a/b/tests", true));
+        assert!(is_match("/target/**", "target/debug/minigrep", false));
+    }
+
+    #[test]
+    fn dir_only_patterns() {
+        assert!(is_match("target/", "target", true));
+        assert!(is_match("target/", "sub/target", true));
+        assert!(!is_match("target/", "target", false));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert_eq!(
+            Some(GlobError::UnclosedClass("[a-z".to_string())),
+            Glob::new("[a-z").err()
+        );
+        assert_eq!(Some(GlobError::Empty), Glob::new("/").err());
+    }
+}
@@ -0,0 +1,110 @@
+use crate::glob::Glob;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 一个 `.gitignore` 或 `.ignore` 文件中的规则，语法和 git 一样:
+///
+/// - 空行和以 `#` 开头的行会被跳过，`\#` 表示以 `#` 开头的模式
+/// - 以 `!` 开头的模式把之前被忽略的文件重新包含进来，`\!` 表示以 `!` 开头的模式
+/// - 行尾的空格会被去掉，除非用 `\ ` 转义
+/// - 同一个文件中，后面的规则优先
+///
+/// 模式本身(`/` 锚定、以 `/` 结尾只匹配目录、`**`)的含义见 [`Glob`]，
+/// 匹配时使用相对于忽略文件所在目录的路径
+#[derive(Debug, Default)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    glob: Glob,
+    /// `!` 开头的规则
+    negated: bool,
+}
+
+impl Ignore {
+    pub fn parse(contents: &str) -> Self {
+        let mut rules = Vec::new();
+        for line in contents.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            let mut line = line;
+            while line.ends_with(' ') && !line.ends_with("\\ ") {
+                line = &line[..line.len() - 1];
+            }
+            let (line, negated) = match line.strip_prefix('!') {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            // 和 git 一样，写错的模式直接跳过
+            if let Ok(glob) = Glob::new(line) {
+                rules.push(Rule { glob, negated });
+            }
+        }
+        Ignore { rules }
+    }
+
+    /// 读取忽略文件，文件不存在或者读不了时返回 `None`
+    pub fn from_file(path: &Path) -> Option<Self> {
+        fs::read_to_string(path)
+            .ok()
+            .map(|contents| Self::parse(&contents))
+    }
+
+    /// 全局的忽略文件，和 git 一样是 `$XDG_CONFIG_HOME/git/ignore`，
+    /// 没有设置 `XDG_CONFIG_HOME` 时是 `~/.config/git/ignore`
+    pub fn global() -> Option<Self> {
+        let config = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Self::from_file(&config.join("git").join("ignore"))
+    }
+
+    /// `path` 是相对于忽略文件所在目录、以 `/` 分隔的路径
+    ///
+    /// 返回 `Some(true)` 表示被忽略，`Some(false)` 表示被 `!` 重新包含，`None` 表示没有规则匹配
+    pub fn matched(&self, path: &str, is_dir: bool) -> Option<bool> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.glob.is_match(path, is_dir))
+            .map(|rule| !rule.negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gitignore_rules() {
+        let ignore = Ignore::parse(
+            "# build output\n\
+             \n\
+             /target\n\
+             *.log\n\
+             !important.log\n\
+             logs/\n\
+             doc/*.html\n\
+             \\#notes  \n\
+             \\!bang\n\
+             trailing\\ \n",
+        );
+        assert_eq!(Some(true), ignore.matched("target", true));
+        assert_eq!(None, ignore.matched("sub/target", true));
+        assert_eq!(Some(true), ignore.matched("a/b/debug.log", false));
+        assert_eq!(Some(false), ignore.matched("a/important.log", false));
+        assert_eq!(Some(true), ignore.matched("src/logs", true));
+        assert_eq!(None, ignore.matched("src/logs", false));
+        assert_eq!(Some(true), ignore.matched("doc/index.html", false));
+        assert_eq!(None, ignore.matched("src/doc/index.html", false));
+        assert_eq!(Some(true), ignore.matched("#notes", false));
+        assert_eq!(Some(true), ignore.matched("!bang", false));
+        assert_eq!(Some(true), ignore.matched("trailing ", false));
+        assert_eq!(None, ignore.matched("# build output", false));
+    }
+}
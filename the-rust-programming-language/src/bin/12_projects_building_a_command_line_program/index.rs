@@ -0,0 +1,406 @@
+use crate::archive;
+use crate::fold::{fold, Folding};
+use crate::replace;
+use crate::walk::{self, Filter};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, Metadata};
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// 索引文件放在建立索引的目录下，是隐藏文件，默认不会被搜索
+pub const INDEX_FILE: &str = ".minigrep-index";
+const MAGIC: &[u8] = b"MINIGREP-INDEX 1\n";
+/// 和搜索时判断二进制文件的方式一样，只检查开头(`BufReader` 默认的缓冲区大小)有没有 `\0`
+const BINARY_CHECK: usize = 8 * 1024;
+
+/// 三元组(trigram)索引，记录每个文件中出现过的所有连续 3 个字节
+///
+/// 一个查询能匹配某个文件，这个文件一定包含查询中的所有三元组，
+/// 所以搜索前可以先用索引排除掉大部分文件，剩下的再用正常的搜索逐行确认。
+/// 文本先按 -i 的 Full 方式折叠再取三元组，这样大小写敏感和不敏感的查询都能用同一份索引。
+///
+/// 文件格式(整数都是小端序):
+/// - `MAGIC`
+/// - 文件数(u32)，每个文件: 路径长度(u32)、路径(相对于索引所在目录，用 `/` 分隔)、
+///   修改时间的秒(u64)和纳秒(u32)、文件大小(u64)
+/// - 三元组数(u32)，按三元组排序，每一项: 三元组(3 字节)、文件列表的字节数(u32)
+/// - 所有三元组的文件列表，每个列表是递增的文件序号，存和前一个序号的差，用 LEB128 变长编码
+#[derive(Debug, Default)]
+pub struct Index {
+    files: Vec<Entry>,
+    /// 路径 -> 文件序号
+    ids: HashMap<String, u32>,
+    /// 三元组以及它的文件列表在 `postings` 中的位置
+    table: Vec<([u8; 3], Range<usize>)>,
+    postings: Vec<u8>,
+}
+
+/// 建立索引时文件的状态，和现在不一样时说明索引已经过期
+#[derive(Debug, PartialEq)]
+struct Entry {
+    path: String,
+    modified: (u64, u32),
+    len: u64,
+}
+
+impl Entry {
+    fn new(path: String, metadata: &Metadata) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        Entry {
+            path,
+            modified: (modified.as_secs(), modified.subsec_nanos()),
+            len: metadata.len(),
+        }
+    }
+}
+
+impl Index {
+    /// 遍历 `root` 中的所有文件(和搜索时使用同样的过滤条件)，建立索引
+    pub fn build(root: &Path, filter: &Filter) -> io::Result<Self> {
+        let mut files = Vec::new();
+        let mut grams: HashMap<[u8; 3], Vec<u32>> = HashMap::new();
+        let mut seen = HashSet::new();
+        for path in walk::files(root, filter)? {
+            let relative = walk::relative(root, &path);
+            if relative == INDEX_FILE {
+                continue;
+            }
+            // 先取修改时间再读内容，读的过程中文件被修改的话，下次搜索时会被当作过期的文件
+            let read = fs::metadata(&path).and_then(|metadata| Ok((metadata, fs::read(&path)?)));
+            let (metadata, bytes) = match read {
+                Ok(read) => read,
+                Err(e) => {
+                    eprintln!("{}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            // 压缩文件和归档的内容要解压之后才知道，不放进索引，每次都搜索
+            if archive::is_container(&bytes) {
+                continue;
+            }
+            let id = files.len() as u32;
+            files.push(Entry::new(relative, &metadata));
+            // 二进制文件搜索时会被跳过，不需要三元组
+            if bytes[..bytes.len().min(BINARY_CHECK)].contains(&0) {
+                continue;
+            }
+            seen.clear();
+            trigrams(&String::from_utf8_lossy(&bytes), &mut seen);
+            for gram in &seen {
+                grams.entry(*gram).or_default().push(id);
+            }
+        }
+
+        let mut grams: Vec<_> = grams.into_iter().collect();
+        grams.sort_unstable_by_key(|(gram, _)| *gram);
+        let mut index = Index {
+            files,
+            ..Default::default()
+        };
+        for (gram, ids) in grams {
+            let start = index.postings.len();
+            let mut last = 0;
+            for id in ids {
+                write_varint(&mut index.postings, id - last);
+                last = id;
+            }
+            index.table.push((gram, start..index.postings.len()));
+        }
+        index.fill_ids();
+        Ok(index)
+    }
+
+    /// 索引中的文件数
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let mut reader = Reader { bytes: &bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a minigrep index, or built by another version",
+            ));
+        }
+        let mut index = Index::default();
+        for _ in 0..reader.u32()? {
+            let len = reader.u32()? as usize;
+            let path = String::from_utf8(reader.take(len)?.to_vec())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            index.files.push(Entry {
+                path,
+                modified: (reader.u64()?, reader.u32()?),
+                len: reader.u64()?,
+            });
+        }
+        let mut start = 0;
+        for _ in 0..reader.u32()? {
+            let gram = reader.take(3)?;
+            let end = start + reader.u32()? as usize;
+            index.table.push(([gram[0], gram[1], gram[2]], start..end));
+            start = end;
+        }
+        index.postings = reader.take(start)?.to_vec();
+        // 先把所有文件列表解码一遍，搜索时就不用再处理损坏的索引
+        for (_, range) in &index.table {
+            index.posting(range.clone())?;
+        }
+        index.fill_ids();
+        Ok(index)
+    }
+
+    /// 先写到临时文件再重命名，正在搜索的进程只会看到旧的或者新的索引
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend((self.files.len() as u32).to_le_bytes());
+        for entry in &self.files {
+            bytes.extend((entry.path.len() as u32).to_le_bytes());
+            bytes.extend(entry.path.as_bytes());
+            bytes.extend(entry.modified.0.to_le_bytes());
+            bytes.extend(entry.modified.1.to_le_bytes());
+            bytes.extend(entry.len.to_le_bytes());
+        }
+        bytes.extend((self.table.len() as u32).to_le_bytes());
+        for (gram, range) in &self.table {
+            bytes.extend(gram);
+            bytes.extend((range.len() as u32).to_le_bytes());
+        }
+        bytes.extend(&self.postings);
+        replace::write_atomic(path, &bytes)
+    }
+
+    fn fill_ids(&mut self) {
+        self.ids = (self.files.iter().enumerate())
+            .map(|(id, entry)| (entry.path.clone(), id as u32))
+            .collect();
+    }
+
+    /// 从 `files` 中去掉不可能匹配的文件，`files` 是在 `root` 中遍历得到的
+    ///
+    /// `queries` 的每一项是一个查询一定包含的三元组，文件可能匹配任意一个查询就保留；
+    /// 有查询没有三元组(太短或者正则中没有固定的字面量)时无法缩小范围。
+    /// 不在索引中，或者修改时间、大小和索引中不一样的文件已经过期，总是保留，交给正常的搜索重新扫描
+    pub fn retain(&self, root: &Path, files: &mut Vec<PathBuf>, queries: &[HashSet<[u8; 3]>]) {
+        if queries.iter().any(HashSet::is_empty) {
+            return;
+        }
+        let candidates: HashSet<u32> = queries
+            .iter()
+            .flat_map(|grams| self.lookup(grams))
+            .collect();
+        files.retain(|path| {
+            let relative = walk::relative(root, path);
+            let Some(&id) = self.ids.get(&relative) else {
+                return true;
+            };
+            let fresh = fs::metadata(path)
+                .is_ok_and(|metadata| Entry::new(relative, &metadata) == self.files[id as usize]);
+            !fresh || candidates.contains(&id)
+        });
+    }
+
+    /// 包含所有 `grams` 的文件
+    fn lookup(&self, grams: &HashSet<[u8; 3]>) -> Vec<u32> {
+        let mut lists = Vec::new();
+        for gram in grams {
+            match self.table.binary_search_by_key(gram, |(gram, _)| *gram) {
+                Ok(i) => lists.push(
+                    (self.posting(self.table[i].1.clone()))
+                        .expect("postings are validated in Index::read"),
+                ),
+                Err(_) => return Vec::new(),
+            }
+        }
+        // 从最短的列表开始求交集
+        lists.sort_unstable_by_key(Vec::len);
+        let Some((first, rest)) = lists.split_first() else {
+            return Vec::new();
+        };
+        first
+            .iter()
+            .copied()
+            .filter(|id| rest.iter().all(|list| list.binary_search(id).is_ok()))
+            .collect()
+    }
+
+    fn posting(&self, range: Range<usize>) -> io::Result<Vec<u32>> {
+        let mut bytes = &self.postings[range];
+        let mut ids = Vec::new();
+        let mut last: u32 = 0;
+        while !bytes.is_empty() {
+            last = (last.checked_add(read_varint(&mut bytes)?))
+                .ok_or_else(|| invalid("file id overflows u32"))?;
+            ids.push(last);
+        }
+        Ok(ids)
+    }
+}
+
+/// 把文本按 Full 方式折叠之后，收集其中所有连续的 3 个字节
+pub fn trigrams(text: &str, grams: &mut HashSet<[u8; 3]>) {
+    let mut window = [0; 3];
+    let mut len = 0;
+    let mut buf = [0; 4];
+    for c in text.chars() {
+        for folded in fold(c, Folding::Full) {
+            for &b in folded.encode_utf8(&mut buf).as_bytes() {
+                window = [window[1], window[2], b];
+                len += 1;
+                if len >= 3 {
+                    grams.insert(window);
+                }
+            }
+        }
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: u32) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// u32 最多占 5 个字节，更长或者在中间被截断都说明索引已经损坏
+fn read_varint(bytes: &mut &[u8]) -> io::Result<u32> {
+    let mut n: u32 = 0;
+    for shift in (0..35).step_by(7) {
+        let Some((&b, rest)) = bytes.split_first() else {
+            return Err(invalid("truncated varint"));
+        };
+        *bytes = rest;
+        n |= ((b & 0x7F) as u32) << shift;
+        if b < 0x80 {
+            return Ok(n);
+        }
+    }
+    Err(invalid("varint longer than 5 bytes"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// 按顺序读取索引文件，长度不够时返回 UnexpectedEof
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < n {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated minigrep index",
+            ));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn grams(text: &str) -> HashSet<[u8; 3]> {
+        let mut grams = HashSet::new();
+        trigrams(text, &mut grams);
+        grams
+    }
+
+    fn names(files: &[PathBuf]) -> Vec<String> {
+        let mut names: Vec<String> = (files.iter())
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn folded_trigrams() {
+        assert_eq!(grams("abcd"), grams("ABCD"));
+        assert_eq!(grams("strasse"), grams("STRAẞE"));
+        assert_eq!(2, grams("abcd").len());
+        assert!(grams("ab").is_empty());
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        let mut out = Vec::new();
+        for n in [0, 127, 128, 300, u32::MAX] {
+            write_varint(&mut out, n);
+        }
+        let mut bytes = out.as_slice();
+        for n in [0, 127, 128, 300, u32::MAX] {
+            assert_eq!(n, read_varint(&mut bytes).unwrap());
+        }
+        assert!(bytes.is_empty());
+
+        let mut bytes: &[u8] = &[0x80; 6];
+        let err = read_varint(&mut bytes).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        let mut bytes: &[u8] = &[0x80, 0x80];
+        let err = read_varint(&mut bytes).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn build_read_and_retain() {
+        let root = env::temp_dir().join(format!("minigrep-index-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        for (file, contents) in [
+            ("a.txt", "How dreary to be somebody!"),
+            ("b.txt", "How public, like a frog"),
+            ("c.txt", "To an admiring bog!"),
+        ] {
+            fs::write(root.join(file), contents).unwrap();
+        }
+        let index = Index::build(&root, &Filter::default()).unwrap();
+        index.write(&root.join(INDEX_FILE)).unwrap();
+        let index = Index::read(&root.join(INDEX_FILE)).unwrap();
+        let all = walk::files(&root, &Filter::default()).unwrap();
+
+        let mut files = all.clone();
+        index.retain(&root, &mut files, &[grams("FROG")]);
+        let frog = names(&files);
+        let mut files = all.clone();
+        index.retain(&root, &mut files, &[grams("frog"), grams("Somebody")]);
+        let either = names(&files);
+        let mut files = all.clone();
+        index.retain(&root, &mut files, &[grams("frog"), grams("a")]);
+        let short = names(&files);
+
+        // 修改之后文件变长，索引过期，总是要重新扫描
+        fs::write(root.join("c.txt"), "To an admiring bog! (no frogs)").unwrap();
+        fs::write(root.join("d.txt"), "a new frog").unwrap();
+        let mut files = walk::files(&root, &Filter::default()).unwrap();
+        index.retain(&root, &mut files, &[grams("public")]);
+        let stale = names(&files);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(vec!["b.txt"], frog);
+        assert_eq!(vec!["a.txt", "b.txt"], either);
+        assert_eq!(3, short.len());
+        assert_eq!(vec!["b.txt", "c.txt", "d.txt"], stale);
+    }
+}
@@ -1,60 +1,730 @@
-use std::env::Args;
+use crate::archive::{self, Archive};
+use crate::args::{Arg, ArgsError, Parser};
+use crate::fold::Folding;
+use crate::glob::Glob;
+use crate::ignore::Ignore;
+use crate::index::{self, Index, INDEX_FILE};
+use crate::matcher::{MatchOptions, Matcher};
+use crate::printer::{Palette, PrintOptions, Printer};
+use crate::regex;
+use crate::replace::{self, Replacement};
+use crate::settings::{self, Settings, Source};
+use crate::thread_pool::ThreadPool;
+use crate::walk::{self, Filter};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::path::Path;
-use std::{env, fs};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
 
-const ENV_VAR_CASE: &str = "CASE_INSENSITIVE";
+/// 用 `-` 表示从标准输入读取
+const STDIN_PATH: &str = "-";
 
+/// 短选项和长选项的对应关系
+const SHORT_FLAGS: [(char, &str); 16] = [
+    ('i', "ignore-case"),
+    ('s', "case-sensitive"),
+    ('v', "invert-match"),
+    ('n', "line-number"),
+    ('c', "count"),
+    ('l', "files-with-matches"),
+    ('w', "word-regexp"),
+    ('E', "regex"),
+    ('e', "regexp"),
+    ('A', "after-context"),
+    ('B', "before-context"),
+    ('C', "context"),
+    ('j', "threads"),
+    ('t', "type"),
+    ('T', "type-not"),
+    ('h', "help"),
+];
+
+#[derive(Default)]
 pub struct Config {
-    query: String,
-    filename: String,
+    /// 查询，用 -e 可以指定多个，任意一个匹配就算匹配
+    patterns: Vec<String>,
+    /// 文件或者目录，可以有多个，`-` 表示标准输入
+    paths: Vec<PathBuf>,
     case_sensitive: bool,
+    /// --fold: 忽略大小写时使用的大小写折叠方式
+    folding: Folding,
+    /// 根据上面的选项编译好的查询，解析完参数之后构建一次
+    matcher: Matcher,
+    /// --replace: 把匹配的部分替换成这个文本
+    replace: Option<Replacement>,
+    /// --in-place: 直接修改文件，而不是输出 diff 预览
+    in_place: bool,
+    /// 把 query 当作正则表达式，而不是普通的子串
+    regex: bool,
+    /// -v: 输出不匹配的行
+    invert: bool,
+    /// -w: 只匹配完整的单词
+    word: bool,
+    /// 搜索目录时的 --include/--exclude 过滤条件
+    filter: Filter,
+    /// --indexed: 先用 `minigrep index build` 建立的索引排除不可能匹配的文件
+    indexed: bool,
+    /// --archives: 搜索 tar/zip 中的每个文件
+    archives: bool,
+    /// 输出格式，包括 -n -c -l 和上下文
+    print: PrintOptions,
+    /// -j: 同时搜索的文件数，默认是 CPU 核数
+    threads: usize,
 }
 
 impl Config {
-    pub(crate) fn new(mut args: Args) -> Result<Self, &'static str> {
-        args.next(); // 略过第1个参数，因为第1个参数是二进制可执行文件的路径
-        let query = match args.next() {
-            None => return Err("Didn't get a query string"),
-            Some(query) => query,
-        };
-        let filename = match args.next() {
-            None => return Err("Didn't get a file name"),
-            Some(file_name) => {
-                if !Path::new(&file_name).try_exists().unwrap_or_default() {
-                    return Err("not available file path");
+    pub(crate) fn new(args: impl Iterator<Item = String>) -> Result<Self, ArgsError> {
+        Config::with_settings(args, Settings::load()?)
+    }
+
+    /// `settings` 是默认值、配置文件和环境变量，命令行中的选项也记录在这里，最后统一应用到 config 上；
+    /// 测试中传入 `Settings::defaults()`，结果就不受本机配置文件和环境变量的影响
+    pub(crate) fn with_settings(
+        args: impl Iterator<Item = String>,
+        mut settings: Settings,
+    ) -> Result<Self, ArgsError> {
+        // 略过第1个参数，因为第1个参数是二进制可执行文件的路径
+        let mut parser = Parser::new(args.skip(1));
+        let mut config = Config::default();
+        let mut positional = Vec::new();
+        let mut replace = None;
+        let mut debug_config = false;
+        while let Some(arg) = parser.next()? {
+            let name = match arg {
+                Arg::Value(value) => {
+                    positional.push(value);
+                    continue;
+                }
+                Arg::Short(short) => match SHORT_FLAGS.iter().find(|(c, _)| *c == short) {
+                    Some((_, name)) => name.to_string(),
+                    None => return Err(ArgsError::UnknownFlag(format!("-{}", short))),
+                },
+                Arg::Long(name) => name,
+            };
+            // -s --no-line-number --no-hidden --ignore
+            if let Some(flag) = settings::negated(&name) {
+                settings.set(flag, Value::Bool(false), Source::CommandLine)?;
+                continue;
+            }
+            match name.as_str() {
+                // -i -n --hidden --no-ignore
+                flag if settings::FLAGS.contains(&flag) => {
+                    settings.set(flag, Value::Bool(true), Source::CommandLine)?
+                }
+                // --fold --color
+                key if settings::CHOICES.iter().any(|(name, _)| *name == key) => {
+                    let flag = format!("--{}", key);
+                    let value = parser.value(&flag)?;
+                    settings
+                        .set(key, Value::from(value.as_str()), Source::CommandLine)
+                        .map_err(|_| ArgsError::InvalidChoice(flag, value))?;
                 }
-                file_name
+                "threads" => {
+                    let threads = match Self::lines(&mut parser, "-j")? {
+                        0 => {
+                            return Err(ArgsError::InvalidNumber("-j".to_string(), "0".to_string()))
+                        }
+                        threads => threads,
+                    };
+                    settings.set("threads", Value::from(threads), Source::CommandLine)?;
+                }
+                "invert-match" => config.invert = true,
+                "count" => config.print.count = true,
+                "files-with-matches" => config.print.files_with_matches = true,
+                "word-regexp" => config.word = true,
+                "regex" => config.regex = true,
+                "regexp" => config.patterns.push(parser.value("-e")?),
+                "after-context" => config.print.after = Self::lines(&mut parser, "-A")?,
+                "before-context" => config.print.before = Self::lines(&mut parser, "-B")?,
+                "context" => {
+                    let lines = Self::lines(&mut parser, "-C")?;
+                    config.print.before = lines;
+                    config.print.after = lines;
+                }
+                "type" | "type-not" => {
+                    let kind = parser.value(&format!("--{}", name))?;
+                    let globs = settings
+                        .globs(&kind)
+                        .ok_or_else(|| ArgsError::UnknownType(kind.clone()))?;
+                    let filter = &mut config.filter;
+                    let target = if name == "type-not" {
+                        &mut filter.exclude
+                    } else {
+                        &mut filter.include
+                    };
+                    for glob in globs {
+                        target.push(Glob::new(glob)?);
+                    }
+                }
+                "json" => config.print.json = true,
+                "replace" => replace = Some(parser.value("--replace")?),
+                "in-place" => config.in_place = true,
+                "indexed" => config.indexed = true,
+                "archives" => config.archives = true,
+                "debug-config" => debug_config = true,
+                "help" => return Err(ArgsError::Help),
+                name if filter_flag(&mut config.filter, name, &mut parser)? => {}
+                _ => return Err(ArgsError::UnknownFlag(format!("--{}", name))),
             }
+        }
+        if debug_config {
+            return Err(ArgsError::DebugConfig(settings.to_string()));
+        }
+
+        config.case_sensitive = !settings.flag("ignore-case");
+        config.print.line_number = settings.flag("line-number");
+        config.filter.hidden = settings.flag("hidden");
+        config.filter.no_ignore = settings.flag("no-ignore");
+        config.folding = match settings.string("fold") {
+            Some("simple") => Folding::Simple,
+            _ => Folding::Full,
         };
-        // 默认是大小写敏感的
-        let case_sensitive = env::var(ENV_VAR_CASE).is_err();
-        Ok(Config {
-            query,
-            filename,
-            case_sensitive,
-        })
+        let color = match settings.string("color") {
+            Some("always") => true,
+            Some("never") => false,
+            // 输出到终端时才加颜色，重定向到文件或者管道时不加
+            _ => io::stdout().is_terminal(),
+        };
+        if color {
+            config.print.palette = settings.palette();
+        }
+        config.threads = settings.number("threads").unwrap_or(1);
+
+        let mut positional = positional.into_iter();
+        // 没有用 -e 指定查询时，第一个位置参数就是查询
+        if config.patterns.is_empty() {
+            config
+                .patterns
+                .push(positional.next().ok_or(ArgsError::MissingQuery)?);
+        }
+        for path in positional {
+            if path != STDIN_PATH && !Path::new(&path).try_exists().unwrap_or_default() {
+                return Err(ArgsError::PathNotFound(path));
+            }
+            config.paths.push(PathBuf::from(path));
+        }
+        if config.paths.is_empty() {
+            return Err(ArgsError::MissingPath);
+        }
+        if !config.filter.no_ignore {
+            config.filter.global = Ignore::global().unwrap_or_default();
+        }
+        // 搜索目录、多个文件或者归档中的文件时，每一行结果前面都加上文件路径
+        config.print.with_path = config.paths.len() > 1
+            || config.archives
+            || config.paths.iter().any(|path| path.is_dir());
+        // JSON 本身就是给程序读的，不需要颜色
+        if config.print.json {
+            config.print.palette = Palette::default();
+        }
+        let options = MatchOptions {
+            regex: config.regex,
+            case_sensitive: config.case_sensitive,
+            folding: config.folding,
+        };
+        config.matcher = Matcher::new(&config.patterns, options)?;
+
+        match replace {
+            // 只有正则才有捕获分组，其他模式下 `$` 没有特殊含义
+            Some(text) if config.regex => config.replace = Some(Replacement::template(&text)),
+            Some(text) => config.replace = Some(Replacement::literal(&text)),
+            None if config.in_place => {
+                return Err(ArgsError::Requires(
+                    "--in-place".to_string(),
+                    "--replace".to_string(),
+                ))
+            }
+            None => {}
+        }
+        if config.replace.is_some() {
+            let conflicts = [
+                (config.invert, "--invert-match"),
+                (config.print.count, "--count"),
+                (config.print.files_with_matches, "--files-with-matches"),
+                (config.print.json, "--json"),
+                (
+                    config.in_place
+                        && config
+                            .paths
+                            .iter()
+                            .any(|path| path.as_os_str() == STDIN_PATH),
+                    STDIN_PATH,
+                ),
+            ];
+            if let Some((_, flag)) = conflicts.iter().find(|(set, _)| *set) {
+                return Err(ArgsError::Conflicts(
+                    "--replace".to_string(),
+                    flag.to_string(),
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    /// 按照配置搜索一段内容，-w 在这里处理
+    fn select<'a>(&self, contents: &'a str) -> Vec<Match<'a>> {
+        let results = search(&self.matcher, contents);
+        if self.word {
+            whole_words(results)
+        } else {
+            results
+        }
+    }
+
+    /// 逐行读取并搜索，内存占用只和最长的一行有关，和文件大小无关，可以用于管道和很大的日志文件
+    ///
+    /// 不是合法 UTF-8 的字节会被替换成 U+FFFD，而不是让整个搜索失败
+    fn search_reader<R: BufRead, W: Write>(
+        &self,
+        mut reader: R,
+        printer: &mut Printer<W>,
+    ) -> io::Result<()> {
+        let mut buf = Vec::new();
+        let mut line_number = 0;
+        while reader.read_until(b'\n', &mut buf)? > 0 {
+            line_number += 1;
+            let text = String::from_utf8_lossy(&buf);
+            // 带着换行符一起搜索，这样空行也能被 lines() 看到
+            let ranges = self.select(&text).pop().map(|result| result.ranges);
+            let selected = ranges.is_some() != self.invert;
+            let line = match text.strip_suffix('\n') {
+                Some(line) => line.strip_suffix('\r').unwrap_or(line),
+                None => &text,
+            };
+            // 正则可能匹配到行尾的换行符，截掉
+            let ranges: Vec<_> = ranges
+                .unwrap_or_default()
+                .into_iter()
+                .map(|range| range.start.min(line.len())..range.end.min(line.len()))
+                .collect();
+            printer.line(line_number, line, selected, &ranges)?;
+            buf.clear();
+        }
+        Ok(())
+    }
+
+    /// 每个查询一定包含的三元组，见 [`Index::retain`]
+    fn trigrams(&self) -> Vec<HashSet<[u8; 3]>> {
+        // -v 输出的是不匹配的行，任何文件都可能有
+        if self.invert {
+            return vec![HashSet::new()];
+        }
+        (self.patterns.iter())
+            .map(|pattern| {
+                let mut grams = HashSet::new();
+                if self.regex {
+                    for literal in regex::Regex::literals(pattern) {
+                        index::trigrams(&literal, &mut grams);
+                    }
+                } else {
+                    index::trigrams(pattern, &mut grams);
+                }
+                grams
+            })
+            .collect()
+    }
+
+    /// 把一行中所有(-w 时只有完整单词的)匹配替换掉，没有匹配时原样返回
+    fn replace_line<'a>(&self, line: &'a str, replacement: &Replacement) -> Cow<'a, str> {
+        let Some(found) = self.select(line).pop() else {
+            return Cow::Borrowed(line);
+        };
+        let mut replaced = String::with_capacity(line.len());
+        let mut last = 0;
+        for range in found.ranges {
+            replaced.push_str(&line[last..range.start]);
+            let groups = self.matcher.captures(line, range.clone());
+            replacement.expand(line, &groups, &mut replaced);
+            last = range.end;
+        }
+        replaced.push_str(&line[last..]);
+        Cow::Owned(replaced)
+    }
+
+    /// -A/-B/-C/-j 后面的数字
+    fn lines<I: Iterator<Item = String>>(
+        parser: &mut Parser<I>,
+        flag: &str,
+    ) -> Result<usize, ArgsError> {
+        let value = parser.value(flag)?;
+        value
+            .parse()
+            .map_err(|_| ArgsError::InvalidNumber(flag.to_string(), value))
+    }
+}
+
+/// `minigrep index build` 的参数
+#[derive(Default)]
+pub struct IndexConfig {
+    /// 要建立索引的目录，索引文件写在每个目录下
+    paths: Vec<PathBuf>,
+    filter: Filter,
+}
+
+impl IndexConfig {
+    /// 第1个参数是子命令 `build`，和 `Config::new` 一样略过
+    pub(crate) fn new(args: impl Iterator<Item = String>) -> Result<Self, ArgsError> {
+        let mut parser = Parser::new(args.skip(1));
+        let mut config = IndexConfig::default();
+        while let Some(arg) = parser.next()? {
+            match arg {
+                Arg::Value(path) if Path::new(&path).is_dir() => {
+                    config.paths.push(PathBuf::from(path))
+                }
+                Arg::Value(path) => return Err(ArgsError::NotADirectory(path)),
+                Arg::Short('h') => return Err(ArgsError::Help),
+                Arg::Short(short) => return Err(ArgsError::UnknownFlag(format!("-{}", short))),
+                Arg::Long(name) if name == "help" => return Err(ArgsError::Help),
+                Arg::Long(name) if filter_flag(&mut config.filter, &name, &mut parser)? => {}
+                Arg::Long(name) => return Err(ArgsError::UnknownFlag(format!("--{}", name))),
+            }
+        }
+        if config.paths.is_empty() {
+            return Err(ArgsError::MissingPath);
+        }
+        if !config.filter.no_ignore {
+            config.filter.global = Ignore::global().unwrap_or_default();
+        }
+        Ok(config)
     }
 }
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(&config.filename)?;
 
-    let results = if config.case_sensitive {
-        search(&config.query, &contents)
+/// 处理搜索和建立索引都支持的 --include/--exclude/--no-ignore/--hidden，不是这几个选项时返回 false
+fn filter_flag<I: Iterator<Item = String>>(
+    filter: &mut Filter,
+    name: &str,
+    parser: &mut Parser<I>,
+) -> Result<bool, ArgsError> {
+    match name {
+        "include" => filter.include.push(Glob::new(&parser.value("--include")?)?),
+        "exclude" => filter.exclude.push(Glob::new(&parser.value("--exclude")?)?),
+        "no-ignore" => filter.no_ignore = true,
+        "hidden" => filter.hidden = true,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+pub fn build_index(config: IndexConfig) -> Result<(), Box<dyn Error>> {
+    for path in &config.paths {
+        let index = Index::build(path, &config.filter)?;
+        let file = path.join(INDEX_FILE);
+        index.write(&file)?;
+        println!("{}: indexed {} files", file.display(), index.file_count());
+    }
+    Ok(())
+}
+
+pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    let trigrams = if config.indexed {
+        config.trigrams()
     } else {
-        search_case_insensitive(&config.query, &contents)
+        Vec::new()
     };
-    /*
-    for line in results {
-        println!("{}", line);
+    // 第二个字段表示是否是命令行里直接指定的文件
+    let mut files = Vec::new();
+    for path in &config.paths {
+        if path.as_os_str() == STDIN_PATH {
+            files.push((path.clone(), true));
+        } else {
+            let mut walked = walk::files(path, &config.filter)?;
+            if config.indexed && path.is_dir() {
+                match Index::read(&path.join(INDEX_FILE)) {
+                    Ok(index) => index.retain(path, &mut walked, &trigrams),
+                    Err(e) => eprintln!(
+                        "{}: can't use the index ({}), run `minigrep index build {}` first",
+                        path.display(),
+                        e,
+                        path.display()
+                    ),
+                }
+            }
+            for file in walked {
+                let explicit = file == *path;
+                files.push((file, explicit));
+            }
+        }
     }
-    */
-    results.iter().for_each(|line| println!("{}", line));
 
-    Ok(())
+    let threads = config.threads;
+    let searcher = Arc::new(Searcher { config });
+    let result = match &searcher.config.replace {
+        Some(replacement) => searcher.replace_all(&files, replacement, io::stdout().lock()),
+        None => searcher.search_all(&files, threads, io::stdout().lock()),
+    };
+    match result {
+        // 输出被关闭了(比如管道后面接了 head)，没必要继续搜索
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// 一次搜索过程中，各个文件共用的状态
+struct Searcher {
+    config: Config,
+}
+
+/// 搜索一个文件的结果
+#[derive(Debug, PartialEq)]
+enum Searched {
+    /// 开头的一块内容中含有 NUL 字节，当作二进制文件跳过
+    Binary,
+    /// 是否输出了匹配行或者上下文行
+    Printed(bool),
+}
+
+impl Searcher {
+    /// 按顺序搜索所有文件，`files` 的第二个字段表示是否是命令行里直接指定的文件
+    ///
+    /// `threads` 大于 1 时把文件分给线程池并行搜索，每个文件的结果先写到自己的缓冲区里，
+    /// 再按文件原来的顺序输出，所以输出和单线程时完全一样
+    fn search_all<W: Write>(
+        self: &Arc<Self>,
+        files: &[(PathBuf, bool)],
+        threads: usize,
+        mut out: W,
+    ) -> io::Result<()> {
+        // 前面的文件输出过内容时，下一个文件的第一个上下文分组前也要加 `--`
+        let mut printed = false;
+        // 标准输入需要边读边输出，不放到线程池里
+        let stdin = files.iter().any(|(file, _)| file.as_os_str() == STDIN_PATH);
+        if threads <= 1 || files.len() <= 1 || stdin {
+            for (file, explicit) in files {
+                let result = if file.as_os_str() == STDIN_PATH {
+                    self.search(io::stdin().lock(), &mut out, &display(file), printed)
+                } else {
+                    File::open(file).and_then(|f| {
+                        self.search(BufReader::new(f), &mut out, &display(file), printed)
+                    })
+                };
+                self.report(file, *explicit, result, &mut printed)?;
+            }
+            return Ok(());
+        }
+
+        let pool = ThreadPool::quiet(threads);
+        // 提前结束时(比如 broken pipe)让还没开始的任务直接返回
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+        for (index, (file, _)) in files.iter().enumerate() {
+            let searcher = Arc::clone(self);
+            let stop = Arc::clone(&stop);
+            let sender = sender.clone();
+            let file = file.clone();
+            pool.execute(move || {
+                if stop.load(Ordering::Relaxed) {
+                    return;
+                }
+                let mut buf = Vec::new();
+                let result = File::open(&file).and_then(|f| {
+                    searcher.search(BufReader::new(f), &mut buf, &display(&file), false)
+                });
+                // 接收端已经不在了，说明不需要这个结果了
+                let _ = sender.send((index, result.map(|searched| (searched, buf))));
+            })
+            .map_err(io::Error::other)?;
+        }
+        drop(sender);
+
+        // 先完成的文件暂存起来，等前面的文件都输出了再输出
+        let mut pending = HashMap::new();
+        let mut next = 0;
+        let result = receiver.iter().try_for_each(|(index, result)| {
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&next) {
+                let (file, explicit) = &files[next];
+                next += 1;
+                let result = result.and_then(|(searched, buf)| {
+                    if searched == Searched::Printed(true)
+                        && printed
+                        && self.config.print.has_context()
+                    {
+                        writeln!(out, "--")?;
+                    }
+                    out.write_all(&buf)?;
+                    Ok(searched)
+                });
+                self.report(file, *explicit, result, &mut printed)?;
+            }
+            Ok(())
+        });
+        stop.store(true, Ordering::Relaxed);
+        result
+    }
+
+    /// 搜索一个文件或者标准输入，`separator` 表示前面的文件是否已经输出过内容
+    ///
+    /// gzip/zstd/xz 压缩的内容先解压；打开 --archives 时，tar/zip 中的每个文件单独搜索，
+    /// 输出时的文件名是 `poems.tar:frogs/well.txt`
+    fn search<R: BufRead, W: Write>(
+        &self,
+        reader: R,
+        mut out: W,
+        name: &str,
+        separator: bool,
+    ) -> io::Result<Searched> {
+        let mut reader = archive::decompress(reader)?;
+        let archive = match self.config.archives {
+            true => Archive::detect(reader.fill_buf()?),
+            false => None,
+        };
+        let Some(archive) = archive else {
+            return self.search_text(reader, out, name, separator);
+        };
+        let mut printed = false;
+        archive.for_each(reader, |path, entry| {
+            let name = format!("{}:{}", name, path);
+            let entry = archive::decompress(entry)?;
+            // 归档中的二进制文件直接跳过
+            if let Searched::Printed(p) =
+                self.search_text(entry, &mut out, &name, separator || printed)?
+            {
+                printed |= p;
+            }
+            Ok(())
+        })?;
+        Ok(Searched::Printed(printed))
+    }
+
+    fn search_text<R: BufRead, W: Write>(
+        &self,
+        mut reader: R,
+        out: W,
+        name: &str,
+        separator: bool,
+    ) -> io::Result<Searched> {
+        if reader.fill_buf()?.contains(&0) {
+            return Ok(Searched::Binary);
+        }
+        let mut printer = Printer::new(out, &self.config.print, name.to_string(), separator);
+        self.config.search_reader(reader, &mut printer)?;
+        Ok(Searched::Printed(printer.finish()?))
+    }
+
+    /// --replace: 默认输出 unified diff 预览，--in-place 时直接修改文件
+    ///
+    /// 没有任何改动的文件不会被写入，保持原样
+    fn replace_all<W: Write>(
+        &self,
+        files: &[(PathBuf, bool)],
+        replacement: &Replacement,
+        mut out: W,
+    ) -> io::Result<()> {
+        for (file, explicit) in files {
+            let result = self.replace(file, replacement, &mut out);
+            self.report(file, *explicit, result, &mut false)?;
+        }
+        Ok(())
+    }
+
+    fn replace<W: Write>(
+        &self,
+        file: &Path,
+        replacement: &Replacement,
+        out: &mut W,
+    ) -> io::Result<Searched> {
+        let bytes = if file.as_os_str() == STDIN_PATH {
+            let mut bytes = Vec::new();
+            io::stdin().lock().read_to_end(&mut bytes)?;
+            bytes
+        } else {
+            fs::read(file)?
+        };
+        if bytes.contains(&0) {
+            return Ok(Searched::Binary);
+        }
+        // 替换之后要把整个文件写回去，不能像搜索时那样把非法的 UTF-8 替换掉
+        let text = String::from_utf8(bytes)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "not valid UTF-8, skipped"))?;
+
+        // 换行符单独保存，写回去的时候原样放回，`\r\n` 不会变成 `\n`
+        let mut old = Vec::new();
+        let mut new = Vec::new();
+        let mut endings = Vec::new();
+        for raw in text.split_inclusive('\n') {
+            let line = match raw.strip_suffix('\n') {
+                Some(line) => line.strip_suffix('\r').unwrap_or(line),
+                None => raw,
+            };
+            old.push(line);
+            new.push(self.config.replace_line(line, replacement));
+            endings.push(&raw[line.len()..]);
+        }
+        let changed = old
+            .iter()
+            .zip(&new)
+            .filter(|(old, new)| *old != new)
+            .count();
+        if changed == 0 {
+            return Ok(Searched::Printed(false));
+        }
+
+        if self.config.in_place {
+            let mut contents = String::with_capacity(text.len());
+            for (line, ending) in new.iter().zip(&endings) {
+                contents.push_str(line);
+                contents.push_str(ending);
+            }
+            replace::write_atomic(file, contents.as_bytes())?;
+            writeln!(out, "{}: {} lines changed", display(file), changed)?;
+        } else {
+            let final_newline = text.ends_with('\n');
+            replace::unified_diff(out, &display(file), &old, &new, final_newline)?;
+        }
+        Ok(Searched::Printed(true))
+    }
+
+    /// 处理一个文件的搜索结果，出错时只有 broken pipe 或者只搜索一个文件时才返回错误
+    fn report(
+        &self,
+        file: &Path,
+        explicit: bool,
+        result: io::Result<Searched>,
+        printed: &mut bool,
+    ) -> io::Result<()> {
+        match result {
+            // 只有命令行里直接指定的文件才提示一下
+            Ok(Searched::Binary) if explicit => {
+                eprintln!("{}: binary file skipped", display(file))
+            }
+            Ok(Searched::Binary) => {}
+            Ok(Searched::Printed(p)) => *printed |= p,
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Err(e),
+            // 目录里难免有读不了的文件，跳过即可
+            Err(e) if self.config.print.with_path => eprintln!("{}: {}", file.display(), e),
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
 }
 
-fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+/// 输出时使用的文件名
+fn display(file: &Path) -> String {
+    if file.as_os_str() == STDIN_PATH {
+        "(standard input)".to_string()
+    } else {
+        file.display().to_string()
+    }
+}
+
+/// 匹配到的一行
+#[derive(Debug, PartialEq)]
+pub struct Match<'a> {
+    /// 行号，从 1 开始
+    pub line_number: usize,
+    pub line: &'a str,
+    /// 每一处匹配在这一行中的字节范围，方便调用方高亮显示(-v 输出的行没有匹配)
+    pub ranges: Vec<Range<usize>>,
+}
+
+/// 用编译好的 `Matcher` 搜索每一行，大小写和正则都由 `Matcher` 处理
+fn search<'a>(matcher: &Matcher, contents: &'a str) -> Vec<Match<'a>> {
     // for循环版本
     /*
     let mut results = Vec::new();
@@ -71,50 +741,78 @@ fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
     // 迭代器版本
     contents
         .lines()
-        .filter(|line| line.contains(query))
+        .zip(1..)
+        .map(|(line, line_number)| Match {
+            line_number,
+            line,
+            ranges: matcher.find_iter(line).collect(),
+        })
+        .filter(|result| !result.ranges.is_empty())
         .collect()
 }
 
-fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = &query.to_lowercase();
-    // for循环版本
-    /*
-    let mut results = Vec::new();
-    // 使用 lines 方法遍历每一行
-    for line in contents.lines() {
-        // 用查询字符串搜索每一行
-        if line.to_lowercase().contains(query) {
-            // 存储匹配的行
-            results.push(line);
-        }
-    }
+/// -w: 只保留前后都不是单词字符的匹配
+fn whole_words(results: Vec<Match>) -> Vec<Match> {
     results
-    */
-    // 迭代器版本
-    contents
-        .lines()
-        .filter(|line| line.to_lowercase().contains(query))
+        .into_iter()
+        .filter_map(|mut result| {
+            let line = result.line;
+            result.ranges.retain(|range| {
+                let before = line.get(..range.start).and_then(|s| s.chars().next_back());
+                let after = line.get(range.end..).and_then(|s| s.chars().next());
+                !before.is_some_and(regex::is_word_char) && !after.is_some_and(regex::is_word_char)
+            });
+            (!result.ranges.is_empty()).then_some(result)
+        })
         .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::env;
+    use std::fs;
+
+    fn config(args: &[&str]) -> Result<Config, ArgsError> {
+        Config::with_settings(
+            ["minigrep"].iter().chain(args).map(|arg| arg.to_string()),
+            Settings::defaults(),
+        )
+    }
+
+    /// 按命令行参数编译出来的 `Matcher`
+    fn matcher(args: &[&str]) -> Matcher {
+        config(args).unwrap().matcher
+    }
+
+    fn lines<'a>(results: &[Match<'a>]) -> Vec<(usize, &'a str)> {
+        results
+            .iter()
+            .map(|result| (result.line_number, result.line))
+            .collect()
+    }
 
     #[test]
     fn case_sensitive() {
-        let query = "duct";
+        let matcher = matcher(&["duct", "-"]);
         let contents = "\
             Rust:\n\
             safe, fast, productive.\n\
             Pick three.\
         ";
-        assert_eq!(vec!["safe, fast, productive."], search(query, contents));
+        // 只比较区间的两端，一个元素的 `vec![15..19]` 会被 clippy 当成笔误
+        let found: Vec<_> = (search(&matcher, contents).into_iter())
+            .map(|m| {
+                let ranges: Vec<_> = m.ranges.iter().map(|r| (r.start, r.end)).collect();
+                (m.line_number, m.line, ranges)
+            })
+            .collect();
+        assert_eq!(vec![(2, "safe, fast, productive.", vec![(15, 19)])], found);
     }
 
     #[test]
     fn case_insensitive() {
-        let query = "rUsT";
+        let matcher = matcher(&["-i", "rUsT", "-"]);
         let contents = "\
             Rust:\n\
             safe, fast, productive.\n\
@@ -123,8 +821,296 @@ mod tests {
         ";
 
         assert_eq!(
-            vec!["Rust:", "Trust me."],
-            search_case_insensitive(query, contents)
+            vec![(1, "Rust:"), (4, "Trust me.")],
+            lines(&search(&matcher, contents))
+        );
+    }
+
+    #[test]
+    fn regex_mode() {
+        let matcher = matcher(&["-E", r"fn \w+\(", "-"]);
+        let contents = "\
+            pub fn run(config: Config) {\n\
+            let f = search;\n\
+            fn search_regex(re: &Regex) {}\
+        ";
+
+        let results = search(&matcher, contents);
+        assert_eq!(
+            vec![
+                (1, "pub fn run(config: Config) {"),
+                (3, "fn search_regex(re: &Regex) {}")
+            ],
+            lines(&results)
+        );
+        assert_eq!(
+            vec![vec![4..11], vec![0..16]],
+            results
+                .into_iter()
+                .map(|result| result.ranges)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    /// 用 `config` 搜索 `contents`，返回输出的内容
+    fn stream(config: &Config, contents: &[u8]) -> String {
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&mut out, &config.print, String::new(), false);
+        config.search_reader(contents, &mut printer).unwrap();
+        printer.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn whole_words_and_invert() {
+        let contents = "\
+            Rust:\n\
+            Trust me.\n\
+            rust_fmt, rust\
+        ";
+
+        assert_eq!(
+            vec![(1, "Rust:"), (3, "rust_fmt, rust")],
+            lines(&whole_words(search(
+                &matcher(&["-i", "rust", "-"]),
+                contents
+            )))
+        );
+        let config = config(&["-vniE", r"\brust", "-"]).unwrap();
+        assert_eq!("2:Trust me.\n", stream(&config, contents.as_bytes()));
+    }
+
+    #[test]
+    fn stream_lossy_lines() {
+        let contents = b"body\r\n\xff body\n\nnobody";
+        let matches = config(&["-n", "body", "-"]).unwrap();
+        assert_eq!(
+            "1:body\n2:\u{FFFD} body\n4:nobody\n",
+            stream(&matches, contents)
+        );
+        let inverted = config(&["-vn", "body", "-"]).unwrap();
+        assert_eq!("3:\n", stream(&inverted, contents));
+    }
+
+    #[test]
+    fn parse_flags() {
+        let config = config(&[
+            "-inw",
+            "--count",
+            "-E",
+            "--include=*.rs",
+            "--fold=simple",
+            "--",
+            "-body",
+            "poem.txt",
+            "src",
+        ])
+        .unwrap();
+        assert_eq!(vec!["-body"], config.patterns);
+        assert_eq!(
+            vec![PathBuf::from("poem.txt"), PathBuf::from("src")],
+            config.paths
+        );
+        assert!(!config.case_sensitive && config.word && config.regex && !config.invert);
+        assert!(config.print.line_number && config.print.count && !config.print.files_with_matches);
+        assert_eq!(1, config.filter.include.len());
+        assert_eq!(Folding::Simple, config.folding);
+    }
+
+    #[test]
+    fn parse_context() {
+        let parsed = config(&["-C2", "-A", "3", "body", "poem.txt"]).unwrap();
+        assert_eq!((2, 3), (parsed.print.before, parsed.print.after));
+        let parsed = config(&["--before-context=1", "body", "poem.txt"]).unwrap();
+        assert_eq!((1, 0), (parsed.print.before, parsed.print.after));
+    }
+
+    #[test]
+    fn command_line_turns_off_config_switches() {
+        let root = env::temp_dir().join(format!("minigrep-negations-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let file = root.join(".minigreprc");
+        fs::write(
+            &file,
+            "ignore-case = true\nline-number = true\nhidden = true\nno-ignore = true\n",
+        )
+        .unwrap();
+        let parse = |args: &[&str]| {
+            let mut settings = Settings::defaults();
+            settings.merge_file(Source::Project(file.clone())).unwrap();
+            let args = ["minigrep"].iter().chain(args).map(|arg| arg.to_string());
+            Config::with_settings(args, settings).unwrap()
+        };
+
+        let enabled = parse(&["body", "-"]);
+        assert!(!enabled.case_sensitive);
+        assert!(enabled.print.line_number);
+        assert!(enabled.filter.hidden && enabled.filter.no_ignore);
+
+        let disabled = parse(&[
+            "-s",
+            "--no-line-number",
+            "--no-hidden",
+            "--ignore",
+            "body",
+            "-",
+        ]);
+        assert!(disabled.case_sensitive);
+        assert!(!disabled.print.line_number);
+        assert!(!disabled.filter.hidden && !disabled.filter.no_ignore);
+
+        // 后出现的选项生效
+        assert!(!parse(&["-s", "-i", "body", "-"]).case_sensitive);
+        assert!(parse(&["-i", "-s", "body", "-"]).case_sensitive);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert_eq!(Some(ArgsError::Help), config(&["body", "--help"]).err());
+        assert_eq!(Some(ArgsError::MissingQuery), config(&["-n"]).err());
+        assert_eq!(Some(ArgsError::MissingPath), config(&["body"]).err());
+        assert_eq!(
+            Some(ArgsError::InvalidNumber("-A".to_string(), "x".to_string())),
+            config(&["-Ax", "body", "poem.txt"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::UnknownFlag("-x".to_string())),
+            config(&["-x", "body", "poem.txt"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::PathNotFound("no-such-file".to_string())),
+            config(&["body", "no-such-file"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::InvalidNumber("-j".to_string(), "0".to_string())),
+            config(&["-j0", "body", "poem.txt"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::InvalidChoice(
+                "--color".to_string(),
+                "red".to_string()
+            )),
+            config(&["--color=red", "body", "poem.txt"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::Requires(
+                "--in-place".to_string(),
+                "--replace".to_string()
+            )),
+            config(&["--in-place", "body", "poem.txt"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::Conflicts(
+                "--replace".to_string(),
+                "--count".to_string()
+            )),
+            config(&["-c", "--replace=x", "body", "poem.txt"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::UnknownType("no-such-type".to_string())),
+            config(&["-t", "no-such-type", "body", "poem.txt"]).err()
+        );
+        // --debug-config 不需要查询和路径
+        assert!(matches!(
+            config(&["-n", "--debug-config"]),
+            Err(ArgsError::DebugConfig(settings)) if settings.contains("line-number = true")
+        ));
+    }
+
+    #[test]
+    fn replace_lines() {
+        let regex = config(&["-E", r"fn (\w+)_v1", "--replace", "fn ${1}_v2 $$1", "-"]).unwrap();
+        let replacement = regex.replace.as_ref().unwrap();
+        assert_eq!(
+            "fn main_v2 $1() {} fn run_v2 $1",
+            regex.replace_line("fn main_v1() {} fn run_v1", replacement)
+        );
+        assert!(matches!(
+            regex.replace_line("fn main() {}", replacement),
+            Cow::Borrowed(_)
+        ));
+
+        // 不是正则时 `$` 没有特殊含义
+        let literal = config(&["-w", "us", "--replace", "$1", "-"]).unwrap();
+        let replacement = literal.replace.as_ref().unwrap();
+        assert_eq!(
+            "pair of $1 - trust",
+            literal.replace_line("pair of us - trust", replacement)
+        );
+    }
+
+    #[test]
+    fn parallel_output_order() {
+        let root = env::temp_dir().join(format!("minigrep-parallel-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        // 文件越靠前越大，先完成的通常是后面的文件
+        let mut files = Vec::new();
+        for i in 0..8 {
+            let file = root.join(format!("{}.txt", i));
+            let line = format!("body {}\nnobody\nsomebody\n", i);
+            fs::write(&file, line.repeat((8 - i) * 1000)).unwrap();
+            files.push((file, false));
+        }
+        fs::write(root.join("binary.dat"), b"body\0").unwrap();
+        files.push((root.join("binary.dat"), true));
+
+        let mut config = config(&["-n", "-C1", "body", "-"]).unwrap();
+        config.print.with_path = true;
+        let searcher = Arc::new(Searcher { config });
+        let search = |threads| {
+            let mut out = Vec::new();
+            searcher.search_all(&files, threads, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let sequential = search(1);
+        let parallel = search(4);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(sequential, parallel);
+        assert!(sequential.find("0.txt").unwrap() < sequential.find("7.txt").unwrap());
+    }
+
+    #[test]
+    fn compressed_files_and_archives() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/bin/12_projects_building_a_command_line_program/fixtures");
+        let search = |args: &[&str]| {
+            let mut args = args.to_vec();
+            args.push(fixtures.to_str().unwrap());
+            let searcher = Arc::new(Searcher {
+                config: config(&args).unwrap(),
+            });
+            let files: Vec<_> = (walk::files(&fixtures, &searcher.config.filter).unwrap())
+                .into_iter()
+                .map(|file| (file, false))
+                .collect();
+            let mut out = Vec::new();
+            searcher.search_all(&files, 1, &mut out).unwrap();
+            String::from_utf8(out)
+                .unwrap()
+                .replace(&format!("{}/", fixtures.display()), "")
+        };
+
+        assert_eq!(
+            "poem.txt.gz:7:How public, like a frog\n\
+             poem.txt.xz:7:How public, like a frog\n\
+             poem.txt.zst:7:How public, like a frog\n",
+            search(&["-n", "frog"])
+        );
+        assert_eq!(
+            "poems.tar:frogs/well.txt.gz:2:cannot talk about the sea.\n\
+             poems.tar.gz:frogs/well.txt.gz:2:cannot talk about the sea.\n\
+             poems.zip:frogs/well.txt.gz:2:cannot talk about the sea.\n",
+            search(&[
+                "-n",
+                "--archives",
+                "sea",
+                "--include",
+                "*.tar*",
+                "--include",
+                "*.zip"
+            ])
         );
     }
 }
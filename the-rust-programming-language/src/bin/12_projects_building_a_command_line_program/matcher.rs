@@ -0,0 +1,397 @@
+use crate::fold::{CaseInsensitive, Folding};
+use crate::regex::{Regex, RegexError};
+use std::collections::VecDeque;
+use std::ops::Range;
+
+/// 编译好的查询，在 `Config` 里只构建一次，之后每一行都直接用
+///
+/// 根据查询的类型选择合适的算法:
+/// - 单个字面量: Boyer-Moore-Horspool，单个字节时逐字(word)扫描
+/// - 多个字面量(`-e a -e b`): Aho-Corasick 自动机，一遍扫描同时查找所有模式
+/// - 忽略大小写: 边折叠边比较，见 `CaseInsensitive`
+/// - 正则表达式: 见 `Regex`
+#[derive(Debug)]
+pub enum Matcher {
+    Literal(Literal),
+    AhoCorasick(AhoCorasick),
+    CaseInsensitive(CaseInsensitive),
+    Regex(Regex),
+    /// 多个忽略大小写的模式或者多个正则，取最靠左的匹配
+    Any(Vec<Matcher>),
+}
+
+/// 没有任何查询，什么都不匹配
+impl Default for Matcher {
+    fn default() -> Self {
+        Matcher::Any(Vec::new())
+    }
+}
+
+/// 构建 `Matcher` 需要的选项
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MatchOptions {
+    pub regex: bool,
+    pub case_sensitive: bool,
+    pub folding: Folding,
+}
+
+impl Matcher {
+    pub fn new(patterns: &[String], options: MatchOptions) -> Result<Self, RegexError> {
+        let single = |pattern: &str| -> Result<Matcher, RegexError> {
+            Ok(match options {
+                MatchOptions {
+                    regex: true,
+                    case_sensitive: true,
+                    ..
+                } => Matcher::Regex(Regex::new(pattern)?),
+                MatchOptions { regex: true, .. } => {
+                    Matcher::Regex(Regex::case_insensitive(pattern)?)
+                }
+                MatchOptions {
+                    case_sensitive: true,
+                    ..
+                } => Matcher::Literal(Literal::new(pattern)),
+                MatchOptions { folding, .. } => {
+                    Matcher::CaseInsensitive(CaseInsensitive::new(pattern, folding))
+                }
+            })
+        };
+        match patterns {
+            [pattern] => single(pattern),
+            _ if options.case_sensitive && !options.regex => {
+                Ok(Matcher::AhoCorasick(AhoCorasick::new(patterns)))
+            }
+            _ => patterns
+                .iter()
+                .map(|pattern| single(pattern))
+                .collect::<Result<_, _>>()
+                .map(Matcher::Any),
+        }
+    }
+
+    /// 从 `start` 开始查找第一个匹配，`start` 必须在字符边界上
+    pub fn find_at(&self, haystack: &str, start: usize) -> Option<Range<usize>> {
+        match self {
+            Matcher::Literal(literal) => literal.find_at(haystack, start),
+            Matcher::AhoCorasick(ac) => ac.find_at(haystack, start),
+            Matcher::CaseInsensitive(query) => query.find_at(haystack, start),
+            Matcher::Regex(re) => re.find_at(haystack, start),
+            // 开头相同时取最长的，和 Aho-Corasick 的结果保持一致
+            Matcher::Any(matchers) => matchers
+                .iter()
+                .filter_map(|matcher| matcher.find_at(haystack, start))
+                .min_by_key(|found| (found.start, usize::MAX - found.end)),
+        }
+    }
+
+    /// `found` 这个匹配的捕获分组，只有正则才有第 0 组(整个匹配)以外的分组
+    #[allow(dead_code)]
+    pub fn captures(&self, haystack: &str, found: Range<usize>) -> Vec<Option<Range<usize>>> {
+        match self {
+            // 从匹配的开头再找一次，得到的是同一个匹配
+            Matcher::Regex(re) => re
+                .captures_at(haystack, found.start)
+                .filter(|groups| groups[0] == Some(found.clone())),
+            // 找出是哪一个模式匹配的
+            Matcher::Any(matchers) => matchers
+                .iter()
+                .find(|matcher| matcher.find_at(haystack, found.start) == Some(found.clone()))
+                .map(|matcher| matcher.captures(haystack, found.clone())),
+            _ => None,
+        }
+        .unwrap_or_else(|| vec![Some(found)])
+    }
+
+    /// 返回所有不重叠的匹配
+    #[allow(dead_code)]
+    pub fn find_iter<'m, 'h>(&'m self, haystack: &'h str) -> Matches<'m, 'h> {
+        Matches {
+            matcher: self,
+            haystack,
+            start: Some(0),
+            last_end: None,
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub struct Matches<'m, 'h> {
+    matcher: &'m Matcher,
+    haystack: &'h str,
+    /// 下一次从哪里开始找，None 表示已经找完了
+    start: Option<usize>,
+    last_end: Option<usize>,
+}
+
+impl Iterator for Matches<'_, '_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let found = self.matcher.find_at(self.haystack, self.start?)?;
+            // 空匹配时必须至少前进一个字符，否则会原地打转
+            self.start = if found.is_empty() {
+                self.haystack[found.end..]
+                    .chars()
+                    .next()
+                    .map(|c| found.end + c.len_utf8())
+            } else {
+                Some(found.end)
+            };
+            // 紧跟在上一个匹配后面的空匹配没有意义，跳过
+            if found.is_empty() && Some(found.end) == self.last_end {
+                continue;
+            }
+            self.last_end = Some(found.end);
+            return Some(found);
+        }
+    }
+}
+
+/// 单个字面量，使用 Boyer-Moore-Horspool 算法
+///
+/// 每次比较窗口的最后一个字节，不匹配时根据这个字节直接跳过一段，
+/// 平均情况下不需要看文本中的每一个字节，查询越长跳得越远。
+/// UTF-8 的字面量在 UTF-8 文本中按字节匹配时，结果一定落在字符边界上
+#[derive(Debug)]
+pub struct Literal {
+    needle: Vec<u8>,
+    /// 窗口最后一个字节是 b 时，窗口可以向后移动 `skip[b]`
+    skip: Box<[usize; 256]>,
+}
+
+impl Literal {
+    pub fn new(needle: &str) -> Self {
+        let needle = needle.as_bytes().to_vec();
+        let mut skip = Box::new([needle.len(); 256]);
+        if let Some((_, init)) = needle.split_last() {
+            for (i, &b) in init.iter().enumerate() {
+                skip[b as usize] = needle.len() - 1 - i;
+            }
+        }
+        Literal { needle, skip }
+    }
+
+    pub fn find_at(&self, haystack: &str, start: usize) -> Option<Range<usize>> {
+        let text = &haystack.as_bytes()[start..];
+        let n = self.needle.len();
+        let found = match n {
+            0 => Some(0),
+            1 => memchr(self.needle[0], text),
+            _ => {
+                let last = self.needle[n - 1];
+                let mut i = 0;
+                loop {
+                    let &b = text.get(i + n - 1)?;
+                    if b == last && text[i..i + n - 1] == self.needle[..n - 1] {
+                        break Some(i);
+                    }
+                    i += self.skip[b as usize];
+                }
+            }
+        };
+        found.map(|i| start + i..start + i + n)
+    }
+}
+
+/// 查找单个字节，和 libc 的 memchr 一样一次检查一个 usize(8 个字节)
+pub fn memchr(needle: u8, haystack: &[u8]) -> Option<usize> {
+    const WORD: usize = std::mem::size_of::<usize>();
+    const LO: usize = usize::MAX / 255; // 0x0101...01
+    const HI: usize = LO << 7; // 0x8080...80
+    let repeated = LO * needle as usize;
+
+    let mut chunks = haystack.chunks_exact(WORD);
+    for (i, chunk) in chunks.by_ref().enumerate() {
+        // 和 needle 相等的字节异或之后是 0，经典的"字中是否有零字节"技巧
+        let word = usize::from_ne_bytes(chunk.try_into().unwrap()) ^ repeated;
+        if word.wrapping_sub(LO) & !word & HI != 0 {
+            let offset = i * WORD;
+            return chunk.iter().position(|&b| b == needle).map(|j| offset + j);
+        }
+    }
+    let offset = haystack.len() - chunks.remainder().len();
+    chunks
+        .remainder()
+        .iter()
+        .position(|&b| b == needle)
+        .map(|j| offset + j)
+}
+
+/// 多个字面量，使用 Aho-Corasick 自动机
+///
+/// 把所有模式建成一棵 trie，再用 BFS 给每个节点加上失败指针，
+/// 并提前算好每个状态遇到每个字节时的下一个状态(相当于一个 DFA)，
+/// 扫描时每个字节只需要查一次表。多个匹配重叠时取最靠左、最长的那个
+#[derive(Debug)]
+pub struct AhoCorasick {
+    /// 状态转移表，用 u32 让表小一些，对缓存更友好
+    next: Vec<[u32; 256]>,
+    /// 每个状态对应的 trie 节点深度，即当前后缀已经匹配的长度
+    depth: Vec<usize>,
+    /// 在这个状态结束的最长模式的长度(包括沿失败指针能到达的状态)
+    output: Vec<Option<usize>>,
+    /// 各个模式的第一个字节
+    first_bytes: Box<[bool; 256]>,
+}
+
+impl AhoCorasick {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Self {
+        let mut ac = AhoCorasick {
+            next: vec![[0; 256]],
+            depth: vec![0],
+            output: vec![None],
+            first_bytes: Box::new([false; 256]),
+        };
+        // 先建 trie，0 表示还没有这条边(根节点的子节点编号不可能是 0)
+        for pattern in patterns {
+            let pattern = pattern.as_ref().as_bytes();
+            if let Some(&b) = pattern.first() {
+                ac.first_bytes[b as usize] = true;
+            }
+            let mut state = 0;
+            for &b in pattern {
+                if ac.next[state][b as usize] == 0 {
+                    ac.next.push([0; 256]);
+                    ac.depth.push(ac.depth[state] + 1);
+                    ac.output.push(None);
+                    ac.next[state][b as usize] = (ac.next.len() - 1) as u32;
+                }
+                state = ac.next[state][b as usize] as usize;
+            }
+            ac.output[state] = Some(pattern.len());
+        }
+
+        // BFS 计算失败指针，同时把缺失的边补成失败指针对应状态的边
+        let mut fail = vec![0; ac.next.len()];
+        let mut queue: VecDeque<usize> = ac.next[0]
+            .iter()
+            .filter(|&&s| s != 0)
+            .map(|&s| s as usize)
+            .collect();
+        while let Some(state) = queue.pop_front() {
+            if ac.output[state].is_none() {
+                ac.output[state] = ac.output[fail[state]];
+            }
+            for b in 0..256 {
+                let child = ac.next[state][b] as usize;
+                if child == 0 {
+                    ac.next[state][b] = ac.next[fail[state]][b];
+                } else {
+                    fail[child] = ac.next[fail[state]][b] as usize;
+                    queue.push_back(child);
+                }
+            }
+        }
+        ac
+    }
+
+    pub fn find_at(&self, haystack: &str, start: usize) -> Option<Range<usize>> {
+        let text = haystack.as_bytes();
+        // 有空模式时，`start` 处就有一个空匹配
+        let mut best = self.output[0].map(|_| start..start);
+        let mut state = 0;
+        let mut i = start;
+        while i < text.len() {
+            // 还在根节点时，先跳过不可能是任何模式开头的字节
+            if state == 0 && best.is_none() {
+                match text[i..].iter().position(|&b| self.first_bytes[b as usize]) {
+                    Some(skip) => i += skip,
+                    None => break,
+                }
+            }
+            state = self.next[state][text[i] as usize] as usize;
+            // 之后的匹配最早也只能从 `i + 1 - depth` 开始，不会比已经找到的更靠左了
+            if let Some(found) = &best {
+                if i + 1 - self.depth[state] > found.start {
+                    break;
+                }
+            }
+            if let Some(len) = self.output[state] {
+                let found = i + 1 - len..i + 1;
+                if best.as_ref().is_none_or(|best| found.start <= best.start) {
+                    best = Some(found);
+                }
+            }
+            i += 1;
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_all(patterns: &[&str], options: MatchOptions, haystack: &str) -> Vec<Range<usize>> {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        Matcher::new(&patterns, options)
+            .unwrap()
+            .find_iter(haystack)
+            .collect()
+    }
+
+    const CASE_SENSITIVE: MatchOptions = MatchOptions {
+        regex: false,
+        case_sensitive: true,
+        folding: Folding::Full,
+    };
+
+    #[test]
+    fn literal_search() {
+        let text = "Are you nobody, too? How dreary to be somebody!";
+        assert_eq!(
+            vec![10..14, 42..46],
+            find_all(&["body"], CASE_SENSITIVE, text)
+        );
+        assert_eq!(vec![19..20], find_all(&["?"], CASE_SENSITIVE, text));
+        assert_eq!(
+            vec![0..12],
+            find_all(&["测试remove"], CASE_SENSITIVE, "测试remove方法")
+        );
+        assert!(find_all(&["Body"], CASE_SENSITIVE, text).is_empty());
+    }
+
+    #[test]
+    fn memchr_every_position() {
+        let text: Vec<u8> = (0..100).collect();
+        for b in [0, 7, 8, 63, 95, 99] {
+            assert_eq!(Some(b as usize), memchr(b, &text));
+        }
+        assert_eq!(None, memchr(200, &text));
+    }
+
+    #[test]
+    fn aho_corasick_leftmost_longest() {
+        assert_eq!(
+            vec![0..3, 4..5, 8..9, 10..11, 13..16, 18..19],
+            find_all(
+                &["he", "she", "s", "hells"],
+                CASE_SENSITIVE,
+                "she sells seashells"
+            )
+        );
+        assert_eq!(
+            vec![4..9],
+            find_all(&["sell", "sells", "ells"], CASE_SENSITIVE, "she sells")
+        );
+        assert_eq!(
+            vec![0..6],
+            find_all(&["bcd", "abcdef"], CASE_SENSITIVE, "abcdef")
+        );
+    }
+
+    #[test]
+    fn multiple_patterns_ignore_case_and_regex() {
+        let text = "Rust pop 中文!";
+        assert_eq!(
+            vec![0..4, 9..15],
+            find_all(&["rust", "中文"], MatchOptions::default(), text)
+        );
+        let regex = MatchOptions {
+            regex: true,
+            ..CASE_SENSITIVE
+        };
+        assert_eq!(vec![0..4, 5..8], find_all(&[r"R\w+", "p.p"], regex, text));
+    }
+}
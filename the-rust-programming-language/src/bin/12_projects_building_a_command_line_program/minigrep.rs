@@ -1,11 +1,42 @@
+mod archive;
+mod args;
+mod fold;
+mod glob;
+mod ignore;
+mod index;
 mod lib_crate;
+mod matcher;
+mod printer;
+mod regex;
+mod replace;
+mod settings;
+// 复用第 20 章 web server 的线程池来并行搜索多个文件
+#[path = "../20_projects_building_a_multithread_web_server/thread_pool.rs"]
+mod thread_pool;
+mod walk;
 
+use crate::args::{ArgsError, USAGE};
 use std::{env, process};
 
 /// 12 一个I/O项目: 构建命令行程序
 ///
 ///   大小写敏感:  cargo r --bin minigrep  body  poem.txt
 /// 大小写不敏感:  CASE_INSENSITIVE=1 cargo r --bin 12  body  poem.txt
+///     正则模式:  cargo r --bin minigrep  -E  '^[A-Z]\w+!'  poem.txt
+///     多个查询:  cargo r --bin minigrep  -e frog  -e bog  poem.txt
+///   显示上下文:  cargo r --bin minigrep  -n -C 1  frog  poem.txt
+///   读标准输入:  cat poem.txt | cargo r --bin minigrep  -n  body  -
+///     更多选项:  cargo r --bin minigrep  -- --help
+///     搜索目录:  cargo r --bin minigrep  Config  src  --include '*.rs'  --exclude target/
+///     并行搜索:  cargo r --bin minigrep  -j 4  fn  src
+///     高亮显示:  cargo r --bin minigrep  --color=always  -n  body  poem.txt
+///    JSON 输出:  cargo r --bin minigrep  --json  body  poem.txt
+///     配置文件:  cargo r --bin minigrep  --debug-config
+///     文件类型:  cargo r --bin minigrep  -t rust  Config  src    (需要在 .minigreprc 中定义 [types])
+///   压缩和归档:  cargo r --bin minigrep  --archives  frog  src/bin/12_projects_building_a_command_line_program/fixtures
+///     建立索引:  cargo r --bin minigrep  index build  src
+///   使用索引搜索:  cargo r --bin minigrep  --indexed  Config  src
+///     查找替换:  cargo r --bin minigrep  -E  'fn (\w+)_i32'  --replace 'fn ${1}'  src  [--in-place]
 ///
 /// 本来这是一个独立的项目，为了代码集中在一起，就不单独搞了
 ///
@@ -59,14 +90,33 @@ use std::{env, process};
 /// 可以将println!打印的内容输出到output.txt文件中
 /// ```
 fn main() {
-    // 这里用到了一个闭包（closure）
-    let config = lib_crate::Config::new(env::args()).unwrap_or_else(|err| {
-        println!("Problem parsing arguments: {}", err);
-        // 非零的退出状态是一个惯例，用来告诉调用程序的进程：该程序以错误状态退出
-        process::exit(1)
-    });
-    if let Err(e) = lib_crate::run(config) {
+    // `minigrep index build <DIR>...` 是单独的子命令，其他情况都是搜索
+    let args: Vec<String> = env::args().collect();
+    let result = if args.get(1..3) == Some(&["index".to_string(), "build".to_string()]) {
+        let config =
+            lib_crate::IndexConfig::new(args.into_iter().skip(2)).unwrap_or_else(usage_error);
+        lib_crate::build_index(config)
+    } else {
+        // 这里用到了一个闭包（closure）
+        let config = lib_crate::Config::new(args.into_iter()).unwrap_or_else(usage_error);
+        lib_crate::run(config)
+    };
+    if let Err(e) = result {
         println!("Application error: {}", e);
         process::exit(1)
     }
 }
+
+fn usage_error<T>(err: ArgsError) -> T {
+    match err {
+        ArgsError::Help => println!("{}", USAGE),
+        ArgsError::DebugConfig(settings) => print!("{}", settings),
+        err => {
+            println!("Problem parsing arguments: {}", err);
+            println!("{}", USAGE);
+            // 非零的退出状态是一个惯例，用来告诉调用程序的进程：该程序以错误状态退出
+            process::exit(1)
+        }
+    }
+    process::exit(0)
+}
@@ -0,0 +1,363 @@
+use ansi_term::{Colour, Style};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// 输出相关的选项
+#[derive(Debug, Default)]
+pub struct PrintOptions {
+    /// -n: 输出行号
+    pub line_number: bool,
+    /// -c: 只输出每个文件匹配的行数
+    pub count: bool,
+    /// -l: 只输出有匹配的文件名
+    pub files_with_matches: bool,
+    /// -B: 匹配行之前的上下文行数
+    pub before: usize,
+    /// -A: 匹配行之后的上下文行数
+    pub after: usize,
+    /// 每一行前面是否加上文件路径，搜索目录或者多个文件时打开
+    pub with_path: bool,
+    /// --color: 各部分的颜色，默认都是 `Style::new()`，也就是不加颜色
+    pub palette: Palette,
+    /// --json: 每个匹配行输出一个 JSON 对象，不输出上下文
+    pub json: bool,
+}
+
+impl PrintOptions {
+    pub fn has_context(&self) -> bool {
+        !self.json && (self.before > 0 || self.after > 0)
+    }
+}
+
+/// 输出各部分使用的颜色
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Palette {
+    pub path: Style,
+    pub line_number: Style,
+    /// `:`、`-` 和 `--`
+    pub separator: Style,
+    pub matched: Style,
+}
+
+impl Palette {
+    /// 和 grep 默认的配色一样
+    pub fn colored() -> Self {
+        Palette {
+            path: Colour::Purple.normal(),
+            line_number: Colour::Green.normal(),
+            separator: Colour::Cyan.normal(),
+            matched: Colour::Red.bold(),
+        }
+    }
+
+    /// 解析配置文件中的颜色，例如 `red`、`bold blue`、`underline 208`，
+    /// 颜色可以是名字或者 256 色中的编号，后面的颜色覆盖前面的
+    pub fn style(spec: &str) -> Option<Style> {
+        let mut style = Style::new();
+        for word in spec.split_whitespace() {
+            style = match word {
+                "bold" => style.bold(),
+                "dimmed" => style.dimmed(),
+                "italic" => style.italic(),
+                "underline" => style.underline(),
+                "black" => style.fg(Colour::Black),
+                "red" => style.fg(Colour::Red),
+                "green" => style.fg(Colour::Green),
+                "yellow" => style.fg(Colour::Yellow),
+                "blue" => style.fg(Colour::Blue),
+                "purple" | "magenta" => style.fg(Colour::Purple),
+                "cyan" => style.fg(Colour::Cyan),
+                "white" => style.fg(Colour::White),
+                n => style.fg(Colour::Fixed(n.parse().ok()?)),
+            };
+        }
+        Some(style)
+    }
+}
+
+/// --json 模式下一行的输出
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    path: &'a str,
+    line: usize,
+    /// 每一处匹配的起止列，从 1 开始按字符计数，不包含结束列
+    columns: Vec<(usize, usize)>,
+    text: &'a str,
+}
+
+/// 逐行接收搜索结果并输出，和 grep 一样处理上下文:
+/// - 匹配行用 `:` 分隔，上下文行用 `-` 分隔
+/// - 重叠或者相邻的上下文窗口会合并在一起
+/// - 不相邻的分组之间输出 `--`
+///
+/// 因为是一行一行处理的，所以只需要缓存最多 `before` 行，不需要把整个文件读进内存
+pub struct Printer<'a, W: Write> {
+    out: W,
+    options: &'a PrintOptions,
+    /// 文件路径，`options.with_path` 打开或者 --json 时输出
+    path: String,
+    /// 第一个分组之前是否也要输出 `--`(前面的文件已经输出过分组了)
+    separator: bool,
+    /// 最近的几行未输出的内容，遇到匹配行时作为前置上下文输出
+    before: VecDeque<(usize, String, Vec<Range<usize>>)>,
+    /// 还要输出多少行后置上下文
+    after: usize,
+    /// 最后输出的行号
+    last_printed: Option<usize>,
+    /// 匹配的行数
+    matched: usize,
+}
+
+impl<'a, W: Write> Printer<'a, W> {
+    pub fn new(out: W, options: &'a PrintOptions, path: String, separator: bool) -> Self {
+        Printer {
+            out,
+            options,
+            path,
+            separator,
+            before: VecDeque::with_capacity(options.before),
+            after: 0,
+            last_printed: None,
+            matched: 0,
+        }
+    }
+
+    /// 处理一行，`selected` 表示这一行是否被选中(匹配，或者 -v 时不匹配)，
+    /// `ranges` 是这一行中匹配的字节范围，用来高亮显示
+    pub fn line(
+        &mut self,
+        line_number: usize,
+        line: &str,
+        selected: bool,
+        ranges: &[Range<usize>],
+    ) -> io::Result<()> {
+        if selected {
+            self.matched += 1;
+            if self.options.count || self.options.files_with_matches {
+                return Ok(());
+            }
+            if self.options.json {
+                return self.write_json(line_number, line, ranges);
+            }
+            let first = self.before.front().map_or(line_number, |(n, _, _)| *n);
+            let separated = match self.last_printed {
+                Some(last) => first > last + 1,
+                None => self.separator,
+            };
+            if separated && self.options.has_context() {
+                writeln!(self.out, "{}", self.options.palette.separator.paint("--"))?;
+            }
+            while let Some((n, context, ranges)) = self.before.pop_front() {
+                self.write_line(n, &context, "-", &ranges)?;
+            }
+            self.write_line(line_number, line, ":", ranges)?;
+            self.after = self.options.after;
+        } else if self.after > 0 {
+            self.after -= 1;
+            self.write_line(line_number, line, "-", ranges)?;
+        } else if self.options.before > 0 {
+            if self.before.len() == self.options.before {
+                self.before.pop_front();
+            }
+            self.before
+                .push_back((line_number, line.to_string(), ranges.to_vec()));
+        }
+        Ok(())
+    }
+
+    /// 一个文件处理完之后调用，返回是否输出过任何行
+    pub fn finish(mut self) -> io::Result<bool> {
+        if self.options.files_with_matches {
+            if self.matched > 0 {
+                let palette = &self.options.palette;
+                writeln!(self.out, "{}", palette.path.paint(&self.path))?;
+            }
+        } else if self.options.count {
+            if self.options.with_path {
+                let palette = &self.options.palette;
+                write!(
+                    self.out,
+                    "{}{}",
+                    palette.path.paint(&self.path),
+                    palette.separator.paint(":")
+                )?;
+            }
+            writeln!(self.out, "{}", self.matched)?;
+        }
+        Ok(self.last_printed.is_some())
+    }
+
+    fn write_line(
+        &mut self,
+        line_number: usize,
+        line: &str,
+        sep: &str,
+        ranges: &[Range<usize>],
+    ) -> io::Result<()> {
+        let palette = &self.options.palette;
+        if self.options.with_path {
+            write!(
+                self.out,
+                "{}{}",
+                palette.path.paint(&self.path),
+                palette.separator.paint(sep)
+            )?;
+        }
+        if self.options.line_number {
+            write!(
+                self.out,
+                "{}{}",
+                palette.line_number.paint(line_number.to_string()),
+                palette.separator.paint(sep)
+            )?;
+        }
+        // 按匹配的范围把一行切开，匹配的部分加上颜色
+        let mut start = 0;
+        for range in ranges {
+            if range.start < start || range.is_empty() {
+                continue;
+            }
+            write!(
+                self.out,
+                "{}{}",
+                &line[start..range.start],
+                palette.matched.paint(&line[range.clone()])
+            )?;
+            start = range.end;
+        }
+        writeln!(self.out, "{}", &line[start..])?;
+        self.last_printed = Some(line_number);
+        Ok(())
+    }
+
+    fn write_json(
+        &mut self,
+        line_number: usize,
+        line: &str,
+        ranges: &[Range<usize>],
+    ) -> io::Result<()> {
+        let column = |offset: usize| line[..offset].chars().count() + 1;
+        let json = JsonLine {
+            path: &self.path,
+            line: line_number,
+            columns: ranges
+                .iter()
+                .map(|range| (column(range.start), column(range.end)))
+                .collect(),
+            text: line,
+        };
+        serde_json::to_writer(&mut self.out, &json)?;
+        writeln!(self.out)?;
+        self.last_printed = Some(line_number);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::slice;
+
+    /// 第 n 行的内容就是 n，`selected` 中的行是匹配行
+    fn print(options: &PrintOptions, total: usize, selected: &[usize]) -> String {
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&mut out, options, String::new(), false);
+        for n in 1..=total {
+            printer
+                .line(n, &n.to_string(), selected.contains(&n), &[])
+                .unwrap();
+        }
+        printer.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn context_windows_merge() {
+        let options = PrintOptions {
+            line_number: true,
+            before: 1,
+            after: 1,
+            ..Default::default()
+        };
+        // 3 和 5 的窗口相邻，合并成一组；9 单独一组
+        assert_eq!(
+            "2-2\n3:3\n4-4\n5:5\n6-6\n--\n8-8\n9:9\n10-10\n",
+            print(&options, 10, &[3, 5, 9])
+        );
+    }
+
+    #[test]
+    fn context_at_file_edges() {
+        let options = PrintOptions {
+            before: 2,
+            after: 3,
+            ..Default::default()
+        };
+        assert_eq!("1\n2\n3\n4\n", print(&options, 4, &[1]));
+        assert_eq!("2\n3\n4\n", print(&options, 4, &[4]));
+    }
+
+    #[test]
+    fn count_and_files_with_matches() {
+        let count = PrintOptions {
+            count: true,
+            ..Default::default()
+        };
+        assert_eq!("2\n", print(&count, 4, &[1, 3]));
+
+        let files = PrintOptions {
+            files_with_matches: true,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&mut out, &files, "poem.txt".to_string(), false);
+        printer
+            .line(1, "body", true, slice::from_ref(&(0..4)))
+            .unwrap();
+        printer.finish().unwrap();
+        assert_eq!("poem.txt\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn colored_output() {
+        let options = PrintOptions {
+            line_number: true,
+            with_path: true,
+            palette: Palette::colored(),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&mut out, &options, "poem.txt".to_string(), false);
+        printer
+            .line(2, "nobody body", true, &[2..6, 7..11])
+            .unwrap();
+        printer.finish().unwrap();
+        assert_eq!(
+            "\x1b[35mpoem.txt\x1b[0m\x1b[36m:\x1b[0m\x1b[32m2\x1b[0m\x1b[36m:\x1b[0m\
+            no\x1b[1;31mbody\x1b[0m \x1b[1;31mbody\x1b[0m\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn json_output() {
+        let options = PrintOptions {
+            json: true,
+            after: 1,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&mut out, &options, "诗.txt".to_string(), false);
+        printer.line(1, "我是谁？", false, &[]).unwrap();
+        printer
+            .line(2, "你是谁？谁", true, &[3..6, 12..15])
+            .unwrap();
+        printer.finish().unwrap();
+        assert_eq!(
+            "{\"path\":\"诗.txt\",\"line\":2,\"columns\":[[2,3],[5,6]],\"text\":\"你是谁？谁\"}\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
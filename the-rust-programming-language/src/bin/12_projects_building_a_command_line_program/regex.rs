@@ -0,0 +1,874 @@
+use crate::fold::{fold, Folding};
+use std::ops::Range;
+
+/// 一个简单的正则表达式引擎
+///
+/// 支持的语法:
+/// - 字面量、`.`(不匹配换行)、转义字符 `\.` `\(` 等
+/// - 锚点: `^` `$` `\b` `\B`
+/// - 字符类: `[abc]` `[^a-z]` `\d` `\D` `\w` `\W` `\s` `\S`
+/// - 分组: `(...)` 捕获分组, `(?:...)` 非捕获分组
+/// - 选择: `a|b`
+/// - 重复: `*` `+` `?` `{n}` `{n,}` `{n,m}`，后面再跟一个 `?` 表示非贪婪，
+///   次数最多是 [`MAX_REPEAT`]，嵌套的重复次数会相乘，编译后最多 [`MAX_PROGRAM`] 条指令
+///
+/// 实现方式是先把表达式解析成语法树，再编译成指令序列，最后用 Pike VM 执行，
+/// 时间复杂度是 O(表达式长度 * 文本长度)，不会出现回溯引擎的指数爆炸
+#[derive(Debug)]
+pub struct Regex {
+    prog: Vec<Inst>,
+    /// 捕获分组的数量(包括整个匹配，即第0组)
+    groups: usize,
+    case_insensitive: bool,
+}
+
+/// `{n,m}` 中的次数上限，编译时每重复一次就要复制一遍子表达式的指令
+pub const MAX_REPEAT: u32 = 1000;
+/// 编译后的指令数上限，`((a{1000}){1000}){1000}` 这样嵌套的重复每个都不超过 [`MAX_REPEAT`]，
+/// 展开之后却有 10 亿条指令
+pub const MAX_PROGRAM: usize = 100_000;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum RegexError {
+    #[error("unexpected end of pattern")]
+    UnexpectedEnd,
+    #[error("unclosed group opened at position {0}")]
+    UnclosedGroup(usize),
+    #[error("unopened group closed at position {0}")]
+    UnopenedGroup(usize),
+    #[error("unclosed character class opened at position {0}")]
+    UnclosedClass(usize),
+    #[error("invalid character class range at position {0}")]
+    InvalidRange(usize),
+    #[error("nothing to repeat at position {0}")]
+    NothingToRepeat(usize),
+    #[error("invalid repetition count at position {0}")]
+    InvalidRepeat(usize),
+    #[error("unknown escape sequence at position {0}")]
+    UnknownEscape(usize),
+    #[error(
+        "pattern is too large, it compiles to more than {} instructions",
+        MAX_PROGRAM
+    )]
+    TooLarge,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, RegexError> {
+        Self::build(pattern, false)
+    }
+
+    /// 忽略大小写的版本
+    pub fn case_insensitive(pattern: &str) -> Result<Self, RegexError> {
+        Self::build(pattern, true)
+    }
+
+    fn build(pattern: &str, case_insensitive: bool) -> Result<Self, RegexError> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 0,
+        };
+        let ast = parser.parse()?;
+        // 先算出指令数再编译，太大时不要分配内存
+        if ast.size() + 3 > MAX_PROGRAM {
+            return Err(RegexError::TooLarge);
+        }
+        let mut compiler = Compiler { prog: Vec::new() };
+        compiler.emit(Inst::Save(0));
+        compiler.compile(&ast);
+        compiler.emit(Inst::Save(1));
+        compiler.emit(Inst::Match);
+        Ok(Regex {
+            prog: compiler.prog,
+            groups: parser.groups + 1,
+            case_insensitive,
+        })
+    }
+
+    /// 匹配时一定会出现的字面量，索引用它们来缩小搜索范围
+    ///
+    /// 只看最外层的连接: 连续的普通字符组成一个字面量，其他节点(包括可以出现 0 次的重复)把它们隔开；
+    /// 最外层是选择(`a|b`)时没有一定会出现的字面量
+    #[allow(dead_code)]
+    pub fn literals(pattern: &str) -> Vec<String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 0,
+        };
+        let nodes = match parser.parse() {
+            Ok(Node::Concat(nodes)) => nodes,
+            Ok(node) => vec![node],
+            Err(_) => return Vec::new(),
+        };
+        let mut literals = vec![String::new()];
+        for node in nodes {
+            match node {
+                Node::Char(c) => literals.last_mut().unwrap().push(c),
+                _ => literals.push(String::new()),
+            }
+        }
+        literals.retain(|literal| !literal.is_empty());
+        literals
+    }
+
+    /// 从 `start` 开始查找第一个匹配，返回匹配的字节范围
+    pub fn find_at(&self, text: &str, start: usize) -> Option<Range<usize>> {
+        let slots = self.exec(text, start)?;
+        Some(slots[0]?..slots[1]?)
+    }
+
+    /// 和 `find_at` 一样，但是返回所有捕获分组的范围，第 0 组是整个匹配，没有参与匹配的分组是 None
+    #[allow(dead_code)]
+    pub fn captures_at(&self, text: &str, start: usize) -> Option<Vec<Option<Range<usize>>>> {
+        let slots = self.exec(text, start)?;
+        Some(
+            slots
+                .chunks(2)
+                .map(|pair| Some(pair[0]?..pair[1]?))
+                .collect(),
+        )
+    }
+
+    /// Pike VM: 所有线程同步地向前推进，靠前的线程优先级更高，
+    /// 一旦有线程匹配成功，优先级比它低的线程全部丢弃
+    fn exec(&self, text: &str, start: usize) -> Option<Vec<Option<usize>>> {
+        let mut clist = Threads::new(self.prog.len());
+        let mut nlist = Threads::new(self.prog.len());
+        let mut matched = None;
+        let mut pos = start;
+        loop {
+            if matched.is_none() {
+                // 还没有找到匹配时，在当前位置启动一个新线程(优先级最低)
+                let mut slots = vec![None; self.groups * 2];
+                self.add_thread(&mut clist, 0, &mut slots, text, pos);
+            }
+            if clist.list.is_empty() && matched.is_some() {
+                break;
+            }
+            let ch = text[pos..].chars().next();
+            nlist.clear();
+            for (pc, slots) in clist.list.iter_mut() {
+                let next = match &self.prog[*pc] {
+                    Inst::Match => {
+                        matched = Some(slots.clone());
+                        break;
+                    }
+                    Inst::Char(c) => ch.filter(|&ch| self.char_eq(*c, ch)),
+                    Inst::Any => ch.filter(|&ch| ch != '\n'),
+                    Inst::Class(class) => ch.filter(|&ch| class.matches(ch, self.case_insensitive)),
+                    _ => None,
+                };
+                if let Some(ch) = next {
+                    self.add_thread(&mut nlist, *pc + 1, slots, text, pos + ch.len_utf8());
+                }
+            }
+            match ch {
+                Some(ch) => pos += ch.len_utf8(),
+                None => break,
+            }
+            std::mem::swap(&mut clist, &mut nlist);
+        }
+        matched
+    }
+
+    /// 沿着空转移(Jmp/Split/Save/Assert)把线程加入列表
+    ///
+    /// 用显式的栈代替递归，`(a?){1000}` 这样很长的空转移链也不会栈溢出；
+    /// 先压入的后处理，所以 Split 先压第二个分支，Save 先压恢复捕获槽的那一步
+    fn add_thread(
+        &self,
+        threads: &mut Threads,
+        pc: usize,
+        slots: &mut [Option<usize>],
+        text: &str,
+        pos: usize,
+    ) {
+        threads.stack.push(Frame::Explore(pc));
+        while let Some(frame) = threads.stack.pop() {
+            let pc = match frame {
+                Frame::Explore(pc) => pc,
+                Frame::Restore(slot, old) => {
+                    slots[slot] = old;
+                    continue;
+                }
+            };
+            if threads.seen[pc] {
+                continue;
+            }
+            threads.seen[pc] = true;
+            match &self.prog[pc] {
+                Inst::Jmp(to) => threads.stack.push(Frame::Explore(*to)),
+                Inst::Split(x, y) => {
+                    threads.stack.push(Frame::Explore(*y));
+                    threads.stack.push(Frame::Explore(*x));
+                }
+                Inst::Save(slot) => {
+                    threads.stack.push(Frame::Restore(*slot, slots[*slot]));
+                    slots[*slot] = Some(pos);
+                    threads.stack.push(Frame::Explore(pc + 1));
+                }
+                Inst::Assert(assertion) => {
+                    if assertion.holds(text, pos) {
+                        threads.stack.push(Frame::Explore(pc + 1));
+                    }
+                }
+                _ => threads.list.push((pc, slots.to_vec())),
+            }
+        }
+    }
+
+    fn char_eq(&self, expected: char, actual: char) -> bool {
+        expected == actual
+            || (self.case_insensitive
+                && fold(expected, Folding::Simple).eq(fold(actual, Folding::Simple)))
+    }
+}
+
+struct Threads {
+    seen: Vec<bool>,
+    list: Vec<(usize, Vec<Option<usize>>)>,
+    /// `add_thread` 用的栈，放在这里是为了重复使用分配好的内存
+    stack: Vec<Frame>,
+}
+
+/// `add_thread` 中还没有完成的一步
+enum Frame {
+    /// 从这条指令开始沿着空转移继续找
+    Explore(usize),
+    /// 离开 Save 之后把捕获槽恢复成原来的值
+    Restore(usize, Option<usize>),
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        Threads {
+            seen: vec![false; len],
+            list: Vec::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.seen.iter_mut().for_each(|seen| *seen = false);
+        self.list.clear();
+    }
+}
+
+#[derive(Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    /// 两个分支，第一个分支优先
+    Split(usize, usize),
+    Jmp(usize),
+    /// 把当前位置记录到指定的捕获槽
+    Save(usize),
+    Match,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Assertion {
+    Start,
+    End,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+impl Assertion {
+    fn holds(self, text: &str, pos: usize) -> bool {
+        let is_word = |c: Option<char>| c.is_some_and(is_word_char);
+        let boundary =
+            || is_word(text[..pos].chars().next_back()) != is_word(text[pos..].chars().next());
+        match self {
+            Assertion::Start => pos == 0,
+            Assertion::End => pos == text.len(),
+            Assertion::WordBoundary => boundary(),
+            Assertion::NotWordBoundary => !boundary(),
+        }
+    }
+}
+
+/// `\w` 对应的单词字符
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+impl Perl {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Perl::Digit => c.is_ascii_digit(),
+            Perl::Word => is_word_char(c),
+            Perl::Space => c.is_whitespace(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    /// `\d` `\w` `\s` 以及它们的否定形式
+    Perl(Perl, bool),
+}
+
+#[derive(Debug, Clone)]
+struct Class {
+    negated: bool,
+    items: Vec<ClassItem>,
+}
+
+impl Class {
+    fn matches(&self, c: char, case_insensitive: bool) -> bool {
+        let contains = |c: char| {
+            self.items.iter().any(|item| match *item {
+                ClassItem::Range(lo, hi) => lo <= c && c <= hi,
+                ClassItem::Perl(perl, negated) => perl.matches(c) != negated,
+            })
+        };
+        let found = contains(c)
+            || (case_insensitive
+                && (c.to_lowercase().any(contains) || c.to_uppercase().any(contains)));
+        found != self.negated
+    }
+}
+
+#[derive(Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    /// 第二个字段是捕获分组的序号，非捕获分组为 None
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+impl Node {
+    /// 编译后的指令数，和 `Compiler::compile` 一一对应，太大时饱和到 `usize::MAX`
+    fn size(&self) -> usize {
+        match self {
+            Node::Char(_) | Node::Any | Node::Class(_) | Node::Assert(_) => 1,
+            Node::Group(inner, None) => inner.size(),
+            Node::Group(inner, Some(_)) => inner.size().saturating_add(2),
+            Node::Concat(items) => items
+                .iter()
+                .fold(0usize, |n, item| n.saturating_add(item.size())),
+            Node::Alternate(branches) => {
+                branches.iter().fold(0usize, |n, branch| {
+                    // 除了最后一个分支，每个分支前面有一个 Split，后面有一个 Jmp
+                    n.saturating_add(branch.size()).saturating_add(2)
+                }) - 2
+            }
+            Node::Repeat { node, min, max, .. } => {
+                let size = node.size();
+                let required = size.saturating_mul(*min as usize);
+                let optional = match max {
+                    None => size.saturating_add(2),
+                    Some(max) => size.saturating_add(1).saturating_mul((max - min) as usize),
+                };
+                required.saturating_add(optional)
+            }
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+}
+
+impl Parser {
+    fn parse(&mut self) -> Result<Node, RegexError> {
+        let node = self.parse_alternate()?;
+        match self.peek() {
+            // parse_alternate 只会在 `)` 或者结尾处停下
+            Some(_) => Err(RegexError::UnopenedGroup(self.pos)),
+            None => Ok(node),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<char, RegexError> {
+        let c = self.peek().ok_or(RegexError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alternate(&mut self) -> Result<Node, RegexError> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.eat('|') {
+            branches.push(self.parse_concat()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alternate(branches)
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, RegexError> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            items.push(self.parse_repeat(atom)?);
+        }
+        Ok(Node::Concat(items))
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, RegexError> {
+        let start = self.pos;
+        let node = match self.next()? {
+            '(' => {
+                let index = if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups)
+                };
+                let inner = self.parse_alternate()?;
+                if !self.eat(')') {
+                    return Err(RegexError::UnclosedGroup(start));
+                }
+                Node::Group(Box::new(inner), index)
+            }
+            '[' => Node::Class(self.parse_class(start)?),
+            '.' => Node::Any,
+            '^' => Node::Assert(Assertion::Start),
+            '$' => Node::Assert(Assertion::End),
+            '*' | '+' | '?' => return Err(RegexError::NothingToRepeat(start)),
+            '\\' => self.parse_escape(start)?,
+            c => Node::Char(c),
+        };
+        Ok(node)
+    }
+
+    fn parse_escape(&mut self, start: usize) -> Result<Node, RegexError> {
+        let c = self.next()?;
+        if let Some(perl) = Self::perl_class(c) {
+            return Ok(Node::Class(Class {
+                negated: false,
+                items: vec![perl],
+            }));
+        }
+        let node = match c {
+            'b' => Node::Assert(Assertion::WordBoundary),
+            'B' => Node::Assert(Assertion::NotWordBoundary),
+            c => Node::Char(Self::escaped_char(c).ok_or(RegexError::UnknownEscape(start))?),
+        };
+        Ok(node)
+    }
+
+    /// `\d` `\w` `\s` 以及它们的否定形式
+    fn perl_class(c: char) -> Option<ClassItem> {
+        let item = match c {
+            'd' => ClassItem::Perl(Perl::Digit, false),
+            'D' => ClassItem::Perl(Perl::Digit, true),
+            'w' => ClassItem::Perl(Perl::Word, false),
+            'W' => ClassItem::Perl(Perl::Word, true),
+            's' => ClassItem::Perl(Perl::Space, false),
+            'S' => ClassItem::Perl(Perl::Space, true),
+            _ => return None,
+        };
+        Some(item)
+    }
+
+    /// `\n` `\t` `\r` 以及被转义的标点符号
+    fn escaped_char(c: char) -> Option<char> {
+        match c {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            c if c.is_alphanumeric() => None,
+            c => Some(c),
+        }
+    }
+
+    fn parse_class(&mut self, start: usize) -> Result<Class, RegexError> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+        // 紧跟在 `[` 或 `[^` 后面的 `]` 当作普通字符
+        let mut first = true;
+        loop {
+            let c = self.next().map_err(|_| RegexError::UnclosedClass(start))?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let lo = if c == '\\' {
+                let escape_pos = self.pos - 1;
+                let c = self.next().map_err(|_| RegexError::UnclosedClass(start))?;
+                if let Some(perl) = Self::perl_class(c) {
+                    items.push(perl);
+                    continue;
+                }
+                Self::escaped_char(c).ok_or(RegexError::UnknownEscape(escape_pos))?
+            } else {
+                c
+            };
+            // `a-z` 形式的范围，`-` 出现在末尾时是普通字符
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                let range_pos = self.pos;
+                self.pos += 1;
+                let hi = match self.next()? {
+                    '\\' => {
+                        let c = self.next()?;
+                        Self::escaped_char(c).ok_or(RegexError::UnknownEscape(range_pos + 1))?
+                    }
+                    c => c,
+                };
+                if hi < lo {
+                    return Err(RegexError::InvalidRange(range_pos));
+                }
+                items.push(ClassItem::Range(lo, hi));
+            } else {
+                items.push(ClassItem::Range(lo, lo));
+            }
+        }
+        Ok(Class { negated, items })
+    }
+
+    fn parse_repeat(&mut self, atom: Node) -> Result<Node, RegexError> {
+        let start = self.pos;
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => match self.parse_counts()? {
+                Some(counts) => counts,
+                // 不是合法的 `{n,m}`，把 `{` 当作普通字符
+                None => return Ok(atom),
+            },
+            _ => return Ok(atom),
+        };
+        // parse_counts 已经移动过 pos 了
+        if self.pos == start {
+            self.pos += 1;
+        }
+        let greedy = !self.eat('?');
+        // 重复后面不能再跟一个重复，`a**` 和 `a{2}{3}` 都是错误；
+        // 不是合法的 `{n,m}` 时和前面一样，把 `{` 当作普通字符
+        let next = self.pos;
+        let repeated = match self.peek() {
+            Some('*' | '+') => true,
+            Some('{') => {
+                let counts = self.parse_counts()?;
+                self.pos = next;
+                counts.is_some()
+            }
+            _ => false,
+        };
+        if repeated {
+            return Err(RegexError::NothingToRepeat(next));
+        }
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        })
+    }
+
+    /// 解析 `{n}` `{n,}` `{n,m}`，解析成功时 pos 会移动到 `}` 之后
+    fn parse_counts(&mut self) -> Result<Option<(u32, Option<u32>)>, RegexError> {
+        let start = self.pos;
+        let rest: String = self.chars[start + 1..].iter().collect();
+        let Some(end) = rest.find('}') else {
+            return Ok(None);
+        };
+        let body = &rest[..end];
+        let parse = |s: &str| s.parse::<u32>().ok();
+        let counts = match body.split_once(',') {
+            None => parse(body).map(|n| (n, Some(n))),
+            Some((min, "")) => parse(min).map(|n| (n, None)),
+            Some((min, max)) => parse(min).zip(parse(max)).map(|(n, m)| (n, Some(m))),
+        };
+        let Some((min, max)) = counts else {
+            return Ok(None);
+        };
+        if max.is_some_and(|max| max < min) || min.max(max.unwrap_or(0)) > MAX_REPEAT {
+            return Err(RegexError::InvalidRepeat(start));
+        }
+        self.pos = start + 1 + body.chars().count() + 1;
+        Ok(Some((min, max)))
+    }
+}
+
+struct Compiler {
+    prog: Vec<Inst>,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> usize {
+        self.prog.push(inst);
+        self.prog.len() - 1
+    }
+
+    fn compile(&mut self, node: &Node) {
+        match node {
+            Node::Char(c) => {
+                self.emit(Inst::Char(*c));
+            }
+            Node::Any => {
+                self.emit(Inst::Any);
+            }
+            Node::Class(class) => {
+                self.emit(Inst::Class(class.clone()));
+            }
+            Node::Assert(assertion) => {
+                self.emit(Inst::Assert(*assertion));
+            }
+            Node::Group(inner, None) => self.compile(inner),
+            Node::Group(inner, Some(index)) => {
+                self.emit(Inst::Save(index * 2));
+                self.compile(inner);
+                self.emit(Inst::Save(index * 2 + 1));
+            }
+            Node::Concat(items) => items.iter().for_each(|item| self.compile(item)),
+            Node::Alternate(branches) => {
+                // split L1, next; L1: branch; jmp end; next: split ...
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i + 1 < branches.len() {
+                        let split = self.emit(Inst::Split(0, 0));
+                        self.compile(branch);
+                        jumps.push(self.emit(Inst::Jmp(0)));
+                        self.prog[split] = Inst::Split(split + 1, self.prog.len());
+                    } else {
+                        self.compile(branch);
+                    }
+                }
+                let end = self.prog.len();
+                jumps
+                    .into_iter()
+                    .for_each(|jmp| self.prog[jmp] = Inst::Jmp(end));
+            }
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.compile(node);
+                }
+                match max {
+                    // L: split body, end; body; jmp L; end:
+                    None => {
+                        let split = self.emit(Inst::Split(0, 0));
+                        self.compile(node);
+                        self.emit(Inst::Jmp(split));
+                        self.patch_split(split, self.prog.len(), *greedy);
+                    }
+                    // 剩下的 max - min 次都是可选的
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.emit(Inst::Split(0, 0)));
+                            self.compile(node);
+                        }
+                        let end = self.prog.len();
+                        splits
+                            .into_iter()
+                            .for_each(|split| self.patch_split(split, end, *greedy));
+                    }
+                }
+            }
+        }
+    }
+
+    fn patch_split(&mut self, split: usize, end: usize, greedy: bool) {
+        self.prog[split] = if greedy {
+            Inst::Split(split + 1, end)
+        } else {
+            Inst::Split(end, split + 1)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+
+    fn find_all(pattern: &str, text: &str) -> Vec<Range<usize>> {
+        Matcher::Regex(Regex::new(pattern).unwrap())
+            .find_iter(text)
+            .collect()
+    }
+
+    #[test]
+    fn literal_and_anchors() {
+        assert_eq!(vec![2..4, 8..10], find_all("st", "Rust: Rust"));
+        assert_eq!(vec![0..4], find_all("^Rust", "Rust: Rust"));
+        assert_eq!(vec![6..10], find_all("Rust$", "Rust: Rust"));
+        assert!(find_all("^safe", "Rust: safe").is_empty());
+    }
+
+    #[test]
+    fn classes_and_repetition() {
+        assert_eq!(
+            vec![4..20],
+            find_all(r"fn \w+\(", "pub fn search_regex(query")
+        );
+        assert_eq!(vec![4..7, 8..10], find_all("[0-9]+", "abc 123 45"));
+        assert_eq!(vec![0..3], find_all("[^ ]{2,3}", "abcd"));
+        assert_eq!(vec![0..2, 2..4], find_all(r"\d{2}", "12345"));
+        assert_eq!(vec![0..1, 1..2, 2..3], find_all("a??.", "abc"));
+    }
+
+    #[test]
+    fn alternation_and_groups() {
+        assert_eq!(vec![0..3, 8..12], find_all("cat|dog|bird", "cat and bird"));
+        assert_eq!(vec![0..6], find_all("(ab)+", "ababab"));
+        assert_eq!(
+            vec![7..11, 13..17],
+            find_all(r"\b(?:fast|safe)\b", "unsafe safe, fast")
+        );
+    }
+
+    #[test]
+    fn case_insensitive_and_unicode() {
+        let re = Matcher::Regex(Regex::case_insensitive("rust").unwrap());
+        assert_eq!(
+            vec![0..4, 7..11],
+            re.find_iter("Rust, trust").collect::<Vec<_>>()
+        );
+        assert_eq!(vec![6..12], find_all("世界", "你好世界"));
+    }
+
+    #[test]
+    fn required_literals() {
+        assert_eq!(vec!["Thread", "::new"], Regex::literals(r"Thread\w+::new"));
+        assert_eq!(vec!["fn ", "_i32("], Regex::literals(r"^fn (\w+)_i32\("));
+        assert_eq!(vec!["a"], Regex::literals("ab*"));
+        assert!(Regex::literals("frog|bog").is_empty());
+    }
+
+    #[test]
+    fn capture_groups() {
+        let re = Regex::new(r"(\w+)@(\w+)(\.com)?").unwrap();
+        assert_eq!(
+            Some(vec![Some(4..13), Some(4..8), Some(9..13), None]),
+            re.captures_at("to: mike@rust", 0)
+        );
+    }
+
+    #[test]
+    fn long_empty_transition_chains() {
+        // 4 万个连续的可选项，递归地沿着空转移加入线程会栈溢出
+        let re = Regex::new("(?:(?:a?){200}){200}b").unwrap();
+        assert_eq!(Some(0..4), re.find_at("aaab", 0));
+        assert_eq!(Some(1..2), re.find_at("xb", 0));
+        assert_eq!(
+            Some(RegexError::TooLarge),
+            Regex::new("((a?){1000}){100}").err()
+        );
+    }
+
+    #[test]
+    fn empty_matches() {
+        assert_eq!(vec![0..0, 1..1, 2..2], find_all("x*", "ab"));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(Some(RegexError::UnclosedGroup(0)), Regex::new("(ab").err());
+        assert_eq!(Some(RegexError::UnopenedGroup(2)), Regex::new("ab)").err());
+        assert_eq!(Some(RegexError::NothingToRepeat(0)), Regex::new("*a").err());
+        assert_eq!(Some(RegexError::UnclosedClass(0)), Regex::new("[ab").err());
+        assert_eq!(Some(RegexError::InvalidRange(2)), Regex::new("[z-a]").err());
+        assert_eq!(
+            Some(RegexError::InvalidRepeat(1)),
+            Regex::new("a{3,1}").err()
+        );
+        assert_eq!(
+            Some(RegexError::InvalidRepeat(1)),
+            Regex::new("a{4000000000}").err()
+        );
+        assert_eq!(
+            Some(RegexError::InvalidRepeat(1)),
+            Regex::new("a{2,1001}").err()
+        );
+        assert!(Regex::new("a{1000}").is_ok());
+        // 重复后面再跟一个重复
+        assert_eq!(
+            Some(RegexError::NothingToRepeat(2)),
+            Regex::new("a**").err()
+        );
+        assert_eq!(
+            Some(RegexError::NothingToRepeat(4)),
+            Regex::new("a{2}{3}").err()
+        );
+        assert_eq!(
+            Some(RegexError::NothingToRepeat(5)),
+            Regex::new("a{2}?{3,}").err()
+        );
+        assert_eq!(vec![0..4], find_all("a{2}{x", "aa{x"));
+    }
+
+    #[test]
+    fn program_size() {
+        for pattern in [
+            "",
+            "a",
+            "(ab|c|)",
+            r"(?:\d{2,5}x)*?",
+            "^(a|bc)+$",
+            "(a?){3}",
+        ] {
+            let ast = Parser {
+                chars: pattern.chars().collect(),
+                pos: 0,
+                groups: 0,
+            }
+            .parse()
+            .unwrap();
+            let regex = Regex::new(pattern).unwrap();
+            assert_eq!(ast.size() + 3, regex.prog.len(), "{pattern}");
+        }
+        // 每一层都不超过 MAX_REPEAT，乘起来就太大了
+        assert_eq!(
+            Some(RegexError::TooLarge),
+            Regex::new("((a{1000}){1000}){1000}").err()
+        );
+        assert_eq!(
+            Some(RegexError::TooLarge),
+            Regex::new("(a{1000}){101}").err()
+        );
+        assert!(Regex::new("(a{1000}){99}").is_ok());
+    }
+}
@@ -0,0 +1,265 @@
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::process;
+
+/// diff 中每个改动前后保留的上下文行数，和 `diff -u` 一样
+const CONTEXT: usize = 3;
+
+/// --replace 的替换文本
+///
+/// 正则模式下可以用 `$1` 或 `${1}` 引用捕获分组，`$0` 是整个匹配，`$$` 表示 `$` 本身；
+/// 其他模式下替换文本原样使用
+#[derive(Debug, PartialEq)]
+pub struct Replacement {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Group(usize),
+}
+
+impl Replacement {
+    pub fn literal(text: &str) -> Self {
+        Replacement {
+            parts: vec![Part::Literal(text.to_string())],
+        }
+    }
+
+    /// 解析 `$n` 和 `${n}`，不认识的 `$` 当作普通字符
+    pub fn template(text: &str) -> Self {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(i) = rest.find('$') {
+            literal.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+            if let Some(after) = rest.strip_prefix('$') {
+                literal.push('$');
+                rest = after;
+                continue;
+            }
+            let (digits, after) = match rest.strip_prefix('{').and_then(|r| r.split_once('}')) {
+                Some((digits, after)) => (digits, after),
+                None => {
+                    let end = rest
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(rest.len());
+                    (&rest[..end], &rest[end..])
+                }
+            };
+            match digits.parse() {
+                Ok(group) => {
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Group(group));
+                    rest = after;
+                }
+                Err(_) => literal.push('$'),
+            }
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Replacement { parts }
+    }
+
+    /// 把替换后的文本追加到 `out`，`groups` 是这个匹配的捕获分组，不存在的分组替换成空字符串
+    pub fn expand(&self, haystack: &str, groups: &[Option<Range<usize>>], out: &mut String) {
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => out.push_str(text),
+                Part::Group(i) => {
+                    if let Some(Some(range)) = groups.get(*i) {
+                        out.push_str(&haystack[range.clone()]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 输出 unified diff 格式的预览，`old` 和 `new` 一一对应，`new` 中的一行替换之后可能包含换行符
+///
+/// 文件的最后一行没有换行符时 `final_newline` 是 `false`，这时在最后一行后面加上
+/// `\ No newline at end of file`，输出可以直接交给 `git apply` 或者 `patch -p1`
+pub fn unified_diff<W: Write>(
+    out: &mut W,
+    path: &str,
+    old: &[&str],
+    new: &[Cow<str>],
+    final_newline: bool,
+) -> io::Result<()> {
+    let changed: Vec<usize> = (0..old.len()).filter(|&i| old[i] != new[i]).collect();
+    if changed.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "--- a/{}", path)?;
+    writeln!(out, "+++ b/{}", path)?;
+
+    // 相邻改动之间的距离不超过两倍上下文时合并成一个 hunk
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        match hunks.last_mut() {
+            Some((_, last)) if i - *last <= 2 * CONTEXT => *last = i,
+            _ => hunks.push((i, i)),
+        }
+    }
+    // 前面的 hunk 让新文件多出(或少了)的行数
+    let mut offset: isize = 0;
+    for (first, last) in hunks {
+        let start = first.saturating_sub(CONTEXT);
+        let end = (last + CONTEXT + 1).min(old.len());
+        let new_len: usize = (start..end).map(|i| new[i].split('\n').count()).sum();
+        writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            start + 1,
+            end - start,
+            (start + 1) as isize + offset,
+            new_len
+        )?;
+        for i in start..end {
+            // 替换不会改变行尾，所以新旧文件的最后一行都没有换行符
+            let no_newline = !final_newline && i == old.len() - 1;
+            if old[i] == new[i] {
+                writeln!(out, " {}", old[i])?;
+                no_newline_marker(out, no_newline)?;
+            } else {
+                writeln!(out, "-{}", old[i])?;
+                no_newline_marker(out, no_newline)?;
+                for line in new[i].split('\n') {
+                    writeln!(out, "+{}", line)?;
+                }
+                no_newline_marker(out, no_newline)?;
+            }
+        }
+        offset += new_len as isize - (end - start) as isize;
+    }
+    Ok(())
+}
+
+fn no_newline_marker<W: Write>(out: &mut W, no_newline: bool) -> io::Result<()> {
+    if no_newline {
+        writeln!(out, "\\ No newline at end of file")?;
+    }
+    Ok(())
+}
+
+/// 原子地替换文件的内容
+///
+/// 先写到同一目录下的临时文件，再 rename 覆盖原文件。同一个文件系统内 rename 是原子的，
+/// 其他进程要么看到旧内容，要么看到新内容；中途出错时原文件保持不变
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    // 符号链接要修改它指向的文件，而不是把链接本身替换成普通文件；文件还不存在时直接创建
+    let path = match fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+        Err(e) => return Err(e),
+    };
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file"));
+    };
+    let temp = dir.join(format!(
+        ".{}.minigrep-{}.tmp",
+        name.to_string_lossy(),
+        process::id()
+    ));
+    let write = || -> io::Result<()> {
+        let mut file = File::create(&temp)?;
+        file.write_all(contents)?;
+        // 保留原文件的权限，比如可执行的脚本
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp, &path)
+    };
+    write().inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn expand(
+        replacement: &Replacement,
+        haystack: &str,
+        groups: &[Option<Range<usize>>],
+    ) -> String {
+        let mut out = String::new();
+        replacement.expand(haystack, groups, &mut out);
+        out
+    }
+
+    #[test]
+    fn template_groups() {
+        let groups = [Some(0..7), Some(3..7), None];
+        let replacement = Replacement::template("fn ${1}_v2 $0 $2$$ $x");
+        assert_eq!(
+            "fn main_v2 fn main $ $x",
+            expand(&replacement, "fn main", &groups)
+        );
+        assert_eq!(
+            "$1",
+            expand(&Replacement::literal("$1"), "fn main", &groups)
+        );
+    }
+
+    #[test]
+    fn diff_hunks() {
+        let old: Vec<String> = (1..=12).map(|n| n.to_string()).collect();
+        let old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let mut new: Vec<Cow<str>> = old.iter().map(|&line| Cow::Borrowed(line)).collect();
+        new[1] = Cow::Borrowed("two\n2");
+        new[11] = Cow::Borrowed("twelve");
+
+        let mut out = Vec::new();
+        unified_diff(&mut out, "n.txt", &old, &new, true).unwrap();
+        assert_eq!(
+            "--- a/n.txt\n+++ b/n.txt\n\
+             @@ -1,5 +1,6 @@\n 1\n-2\n+two\n+2\n 3\n 4\n 5\n\
+             @@ -9,4 +10,4 @@\n 9\n 10\n 11\n-12\n+twelve\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        // 最后一行没有换行符
+        let mut out = Vec::new();
+        unified_diff(&mut out, "n.txt", &old, &new, false).unwrap();
+        assert!(String::from_utf8(out).unwrap().ends_with(
+            " 11\n-12\n\\ No newline at end of file\n\
+             +twelve\n\\ No newline at end of file\n"
+        ));
+        new[10] = Cow::Borrowed("eleven");
+        new[11] = Cow::Borrowed("12");
+        let mut out = Vec::new();
+        unified_diff(&mut out, "n.txt", &old, &new, false).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("-11\n+eleven\n 12\n\\ No newline at end of file\n"));
+    }
+
+    #[test]
+    fn atomic_write_keeps_permissions() {
+        let path = env::temp_dir().join(format!("minigrep-replace-{}.txt", process::id()));
+        fs::write(&path, "old\n").unwrap();
+        let permissions = fs::metadata(&path).unwrap().permissions();
+
+        write_atomic(&path, b"new\n").unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        let after = fs::metadata(&path).unwrap().permissions();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!("new\n", contents);
+        assert_eq!(permissions, after);
+    }
+}
@@ -0,0 +1,408 @@
+use crate::printer::Palette;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::{env, fs, thread};
+
+/// 配置文件的名字: 用户配置是 `~/.minigreprc`，项目配置是从当前目录往上找到的第一个 `.minigreprc`
+const CONFIG_FILE: &str = ".minigreprc";
+/// 用另一个文件代替 `~/.minigreprc`
+const ENV_CONFIG: &str = "MINIGREP_CONFIG";
+/// 设置了这个环境变量(值是什么都可以)就忽略大小写
+pub const ENV_CASE: &str = "CASE_INSENSITIVE";
+const ENV_COLOR: &str = "MINIGREP_COLOR";
+const ENV_THREADS: &str = "MINIGREP_THREADS";
+
+/// 配置文件中可以设置的开关，和同名的命令行选项含义一样
+pub const FLAGS: [&str; 4] = ["ignore-case", "line-number", "hidden", "no-ignore"];
+/// 关掉 `FLAGS` 中开关的命令行选项，配置文件或者环境变量打开的开关也能在命令行中关掉
+pub const NEGATIONS: [(&str, &str); 4] = [
+    ("case-sensitive", "ignore-case"),
+    ("no-line-number", "line-number"),
+    ("no-hidden", "hidden"),
+    ("ignore", "no-ignore"),
+];
+
+/// 找到命令行选项 `option` 关掉的开关
+pub fn negated(option: &str) -> Option<&'static str> {
+    NEGATIONS
+        .iter()
+        .find(|(name, _)| *name == option)
+        .map(|(_, flag)| *flag)
+}
+/// 配置文件中可以设置的、只有几种取值的选项
+pub const CHOICES: [(&str, &[&str]); 2] = [
+    ("fold", &["full", "simple"]),
+    ("color", &["auto", "always", "never"]),
+];
+/// `[colors]` 中可以设置的部分
+const COLORS: [&str; 4] = ["path", "line-number", "separator", "match"];
+
+/// 一项设置来自哪里，按优先级从低到高排列
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Default,
+    User(PathBuf),
+    Project(PathBuf),
+    Env(&'static str),
+    CommandLine,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::User(path) => write!(f, "user config {}", path.display()),
+            Source::Project(path) => write!(f, "project config {}", path.display()),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum SettingsError {
+    #[error("{0}: {1}")]
+    Parse(Source, String),
+    #[error("{1}: unknown setting '{0}'")]
+    UnknownKey(String, Source),
+    #[error("{2}: '{0}' should be {1}")]
+    InvalidValue(String, String, Source),
+}
+
+/// 合并之后的设置
+///
+/// 配置文件可以是 TOML 或者 JSON(内容以 `{` 开头)，例如:
+///
+/// ```toml
+/// line-number = true
+/// color = "always"
+/// threads = 4
+///
+/// [types]
+/// rust = "*.rs"
+/// web = ["*.html", "*.css", "*.js"]
+///
+/// [colors]
+/// path = "bold blue"
+/// match = "black 214"
+/// ```
+///
+/// 键是 `line-number`、`types.rust`、`colors.match` 这样的路径，
+/// 按 默认值 < 用户配置 < 项目配置 < 环境变量 < 命令行 的顺序，后面的覆盖前面的
+#[derive(Debug, Default)]
+pub struct Settings {
+    values: BTreeMap<String, (Value, Source)>,
+}
+
+impl Settings {
+    /// 默认值，然后依次合并用户配置、项目配置和环境变量
+    pub fn load() -> Result<Self, SettingsError> {
+        let mut settings = Settings::defaults();
+        let user = match env::var_os(ENV_CONFIG) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => env::var_os("HOME").map(|home| Path::new(&home).join(CONFIG_FILE)),
+        };
+        if let Some(user) = user.as_ref().filter(|path| path.is_file()) {
+            settings.merge_file(Source::User(user.clone()))?;
+        }
+        // 在 home 目录下运行时，项目配置和用户配置是同一个文件
+        let project = env::current_dir().ok().and_then(|dir| {
+            (dir.ancestors())
+                .map(|dir| dir.join(CONFIG_FILE))
+                .find(|path| path.is_file())
+        });
+        if let Some(project) = project.filter(|project| !same_file(project, user.as_deref())) {
+            settings.merge_file(Source::Project(project))?;
+        }
+        settings.merge_env()?;
+        Ok(settings)
+    }
+
+    /// 只有默认值，不读取配置文件和环境变量
+    pub fn defaults() -> Self {
+        let mut settings = Settings::default();
+        for flag in FLAGS {
+            settings.insert(flag, Value::Bool(false), Source::Default);
+        }
+        for (key, choices) in CHOICES {
+            settings.insert(key, Value::from(choices[0]), Source::Default);
+        }
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        settings.insert("threads", Value::from(threads), Source::Default);
+        settings
+    }
+
+    fn insert(&mut self, key: &str, value: Value, source: Source) {
+        self.values.insert(key.to_string(), (value, source));
+    }
+
+    /// 读取并合并一个配置文件，`source` 是 `Source::User` 或者 `Source::Project`
+    pub fn merge_file(&mut self, source: Source) -> Result<(), SettingsError> {
+        let (Source::User(path) | Source::Project(path)) = &source else {
+            unreachable!("only config files are read");
+        };
+        let contents = fs::read_to_string(path)
+            .map_err(|e| SettingsError::Parse(source.clone(), e.to_string()))?;
+        let value = parse(&contents).map_err(|e| SettingsError::Parse(source.clone(), e))?;
+        self.merge(value, source)
+    }
+
+    /// 合并一个配置文件的内容，同时检查键和值
+    pub fn merge(&mut self, value: Value, source: Source) -> Result<(), SettingsError> {
+        let Value::Object(table) = value else {
+            return Err(SettingsError::Parse(source, "expected a table".to_string()));
+        };
+        for (key, value) in table {
+            match key.as_str() {
+                "types" => {
+                    for (name, globs) in Self::table(&key, value, &source)? {
+                        // 一个 glob 可以直接写成字符串
+                        let globs = match globs {
+                            Value::String(glob) => Value::Array(vec![Value::String(glob)]),
+                            globs => globs,
+                        };
+                        let valid = globs.as_array().is_some_and(|globs| {
+                            !globs.is_empty() && globs.iter().all(Value::is_string)
+                        });
+                        let key = format!("types.{}", name);
+                        if !valid {
+                            return Err(invalid(&key, "a glob or a list of globs", &source));
+                        }
+                        self.insert(&key, globs, source.clone());
+                    }
+                }
+                "colors" => {
+                    for (part, style) in Self::table(&key, value, &source)? {
+                        let key = format!("colors.{}", part);
+                        if !COLORS.contains(&part.as_str()) {
+                            return Err(SettingsError::UnknownKey(key, source));
+                        }
+                        if style.as_str().and_then(Palette::style).is_none() {
+                            return Err(invalid(&key, "a style like \"bold red\"", &source));
+                        }
+                        self.insert(&key, style, source.clone());
+                    }
+                }
+                _ => self.set(&key, value, source.clone())?,
+            }
+        }
+        Ok(())
+    }
+
+    fn table(
+        key: &str,
+        value: Value,
+        source: &Source,
+    ) -> Result<Map<String, Value>, SettingsError> {
+        match value {
+            Value::Object(table) => Ok(table),
+            _ => Err(invalid(key, "a table", source)),
+        }
+    }
+
+    /// 设置一个开关或者选项的值，命令行中的选项也用它记录下来
+    pub fn set(&mut self, key: &str, value: Value, source: Source) -> Result<(), SettingsError> {
+        let valid = if FLAGS.contains(&key) {
+            value.is_boolean()
+        } else if let Some((_, choices)) = CHOICES.iter().find(|(name, _)| *name == key) {
+            if !value.as_str().is_some_and(|value| choices.contains(&value)) {
+                return Err(invalid(
+                    key,
+                    &format!("one of {}", choices.join(", ")),
+                    &source,
+                ));
+            }
+            true
+        } else if key == "threads" {
+            value.as_u64().is_some_and(|threads| threads > 0)
+        } else {
+            return Err(SettingsError::UnknownKey(key.to_string(), source));
+        };
+        if !valid {
+            let expected = if key == "threads" {
+                "a positive number"
+            } else {
+                "true or false"
+            };
+            return Err(invalid(key, expected, &source));
+        }
+        self.insert(key, value, source);
+        Ok(())
+    }
+
+    fn merge_env(&mut self) -> Result<(), SettingsError> {
+        // 和原来一样，只要设置了就算，不看值是什么
+        if env::var_os(ENV_CASE).is_some() {
+            self.set("ignore-case", Value::Bool(true), Source::Env(ENV_CASE))?;
+        }
+        if let Ok(color) = env::var(ENV_COLOR) {
+            self.set("color", Value::String(color), Source::Env(ENV_COLOR))?;
+        }
+        if let Ok(threads) = env::var(ENV_THREADS) {
+            let threads = threads
+                .parse::<u64>()
+                .map_or(Value::String(threads), Value::from);
+            self.set("threads", threads, Source::Env(ENV_THREADS))?;
+        }
+        Ok(())
+    }
+
+    pub fn flag(&self, key: &str) -> bool {
+        self.values.get(key).and_then(|(value, _)| value.as_bool()) == Some(true)
+    }
+
+    pub fn string(&self, key: &str) -> Option<&str> {
+        self.values.get(key).and_then(|(value, _)| value.as_str())
+    }
+
+    pub fn number(&self, key: &str) -> Option<usize> {
+        (self.values.get(key))
+            .and_then(|(value, _)| value.as_u64())
+            .map(|n| n as usize)
+    }
+
+    /// `--type` 使用的文件类型对应的 glob
+    pub fn globs(&self, name: &str) -> Option<Vec<&str>> {
+        let (globs, _) = self.values.get(&format!("types.{}", name))?;
+        Some(globs.as_array()?.iter().filter_map(Value::as_str).collect())
+    }
+
+    /// 在默认的配色上应用 `[colors]` 中的设置
+    pub fn palette(&self) -> Palette {
+        let mut palette = Palette::colored();
+        for (part, style) in [
+            ("path", &mut palette.path),
+            ("line-number", &mut palette.line_number),
+            ("separator", &mut palette.separator),
+            ("match", &mut palette.matched),
+        ] {
+            if let Some(spec) = self.string(&format!("colors.{}", part)) {
+                *style = Palette::style(spec).unwrap_or(*style);
+            }
+        }
+        palette
+    }
+}
+
+/// `--debug-config` 的输出: 每一项设置的值以及它来自哪里
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines: Vec<(String, &Source)> = (self.values.iter())
+            .map(|(key, (value, source))| (format!("{} = {}", key, value), source))
+            .collect();
+        let width = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+        for (line, source) in lines {
+            writeln!(f, "{:width$}  # {}", line, source, width = width)?;
+        }
+        Ok(())
+    }
+}
+
+/// 内容以 `{` 开头时按 JSON 解析，否则按 TOML 解析
+fn parse(contents: &str) -> Result<Value, String> {
+    if contents.trim_start().starts_with('{') {
+        return serde_json::from_str(contents).map_err(|e| e.to_string());
+    }
+    let table: toml::Table = toml::from_str(contents).map_err(|e| e.to_string())?;
+    serde_json::to_value(table).map_err(|e| e.to_string())
+}
+
+fn invalid(key: &str, expected: &str, source: &Source) -> SettingsError {
+    SettingsError::InvalidValue(key.to_string(), expected.to_string(), source.clone())
+}
+
+fn same_file(path: &Path, other: Option<&Path>) -> bool {
+    other.is_some_and(|other| match (path.canonicalize(), other.canonicalize()) {
+        (Ok(path), Ok(other)) => path == other,
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> Source {
+        Source::User(PathBuf::from("~/.minigreprc"))
+    }
+
+    fn project() -> Source {
+        Source::Project(PathBuf::from("/repo/.minigreprc"))
+    }
+
+    #[test]
+    fn toml_and_json_layers() {
+        let mut settings = Settings::defaults();
+        let toml = "line-number = true\n\
+                    color = \"always\"\n\
+                    [types]\n\
+                    rust = \"*.rs\"\n\
+                    web = [\"*.html\", \"*.css\"]\n\
+                    [colors]\n\
+                    match = \"bold 208\"\n";
+        settings.merge(parse(toml).unwrap(), user()).unwrap();
+        let json = r#"{ "color": "never", "threads": 2, "types": { "rust": ["*.rs", "*.ron"] } }"#;
+        settings.merge(parse(json).unwrap(), project()).unwrap();
+        settings
+            .set("threads", Value::from(8), Source::CommandLine)
+            .unwrap();
+
+        assert!(settings.flag("line-number"));
+        assert!(!settings.flag("hidden"));
+        assert_eq!(Some("never"), settings.string("color"));
+        assert_eq!(Some(8), settings.number("threads"));
+        assert_eq!(Some(vec!["*.rs", "*.ron"]), settings.globs("rust"));
+        assert_eq!(Some(vec!["*.html", "*.css"]), settings.globs("web"));
+        assert_eq!(None, settings.globs("go"));
+        assert_eq!(Palette::style("bold 208"), Some(settings.palette().matched));
+
+        let dump = settings.to_string();
+        let line = |key: &str| {
+            (dump.lines())
+                .find(|line| line.starts_with(key))
+                .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        };
+        assert_eq!(
+            Some("color = \"never\" # project config /repo/.minigreprc".to_string()),
+            line("color")
+        );
+        assert_eq!(
+            Some("threads = 8 # command line".to_string()),
+            line("threads")
+        );
+        assert_eq!(Some("hidden = false # default".to_string()), line("hidden"));
+        assert_eq!(
+            Some("types.web = [\"*.html\",\"*.css\"] # user config ~/.minigreprc".to_string()),
+            line("types.web")
+        );
+    }
+
+    #[test]
+    fn invalid_settings() {
+        let mut settings = Settings::defaults();
+        let mut merge = |contents: &str| settings.merge(parse(contents).unwrap(), user());
+        assert_eq!(
+            Err(SettingsError::UnknownKey("colour".to_string(), user())),
+            merge("colour = \"always\"")
+        );
+        assert_eq!(
+            Err(invalid("color", "one of auto, always, never", &user())),
+            merge("color = \"red\"")
+        );
+        assert_eq!(
+            Err(invalid("threads", "a positive number", &user())),
+            merge("threads = 0")
+        );
+        assert_eq!(
+            Err(invalid(
+                "colors.match",
+                "a style like \"bold red\"",
+                &user()
+            )),
+            merge("[colors]\nmatch = \"sparkly\"")
+        );
+        assert!(parse("line-number = ").is_err());
+    }
+}
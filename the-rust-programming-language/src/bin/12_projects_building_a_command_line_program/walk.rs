@@ -0,0 +1,321 @@
+use crate::glob::Glob;
+use crate::ignore::Ignore;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// 遍历目录时使用的过滤条件
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// 非空时，只搜索文件名(或路径)匹配其中任意一个 glob 的文件
+    pub include: Vec<Glob>,
+    /// 匹配的文件会被跳过，匹配的目录不会进入
+    pub exclude: Vec<Glob>,
+    /// --no-ignore: 不读取 `.gitignore`、`.ignore` 和全局的忽略文件
+    pub no_ignore: bool,
+    /// --hidden: 也搜索以 `.` 开头的隐藏文件和目录
+    pub hidden: bool,
+    /// 全局的忽略文件，优先级最低
+    pub global: Ignore,
+}
+
+impl Filter {
+    fn accepts(&self, relative: &str, is_dir: bool) -> bool {
+        if self
+            .exclude
+            .iter()
+            .any(|glob| glob.is_match(relative, is_dir))
+        {
+            return false;
+        }
+        // include 只对文件生效，否则目录都进不去了
+        is_dir
+            || self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|glob| glob.is_match(relative, is_dir))
+    }
+}
+
+/// 返回需要搜索的所有文件
+///
+/// `root` 是文件时直接返回它本身(不受过滤条件影响)，是目录时递归遍历，
+/// 同一目录下的条目按文件名排序，保证每次输出的顺序都一样。
+///
+/// 遍历时会读取每一层目录中的 `.gitignore` 和 `.ignore`，`root` 在 git 仓库中时，
+/// 还会读取 `root` 到仓库根目录之间的上层目录中的忽略文件。
+/// 优先级从高到低: 越深的目录越优先，同一目录中 `.ignore` 比 `.gitignore` 优先，最后是全局的忽略文件
+pub fn files(root: &Path, filter: &Filter) -> io::Result<Vec<PathBuf>> {
+    if !root.is_dir() {
+        return Ok(vec![root.to_path_buf()]);
+    }
+    let mut walker = Walker {
+        root,
+        filter,
+        scopes: Vec::new(),
+        repo_root: String::new(),
+        files: Vec::new(),
+    };
+    if !filter.no_ignore {
+        walker.enter_repo()?;
+    }
+    walker.visit(root)?;
+    Ok(walker.files)
+}
+
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// 一个忽略文件，以及它所在的位置
+struct Scope {
+    ignore: Ignore,
+    /// 忽略文件所在的目录相对于 `root` 的路径，以 `/` 结尾，在 `root` 或者上层目录中时为空
+    dir: String,
+    /// `root` 相对于忽略文件所在目录的路径，以 `/` 结尾，在 `root` 或者下层目录中时为空
+    up: String,
+}
+
+impl Scope {
+    fn matched(&self, relative: &str, is_dir: bool) -> Option<bool> {
+        let path = relative.strip_prefix(&self.dir)?;
+        if self.up.is_empty() {
+            self.ignore.matched(path, is_dir)
+        } else {
+            self.ignore.matched(&format!("{}{}", self.up, path), is_dir)
+        }
+    }
+}
+
+struct Walker<'a> {
+    root: &'a Path,
+    filter: &'a Filter,
+    /// 当前目录及其上层目录中的忽略文件，后面的优先级更高
+    scopes: Vec<Scope>,
+    /// `root` 相对于仓库根目录的路径，全局的忽略文件相对于仓库根目录匹配
+    repo_root: String,
+    files: Vec<PathBuf>,
+}
+
+impl Walker<'_> {
+    /// 找到 `root` 所在的 git 仓库，读取 `root` 之外的上层目录中的忽略文件
+    fn enter_repo(&mut self) -> io::Result<()> {
+        let root = self.root.canonicalize()?;
+        let Some(repo) = root.ancestors().find(|dir| dir.join(".git").exists()) else {
+            return Ok(());
+        };
+        // 从仓库根目录开始，越往下优先级越高
+        let mut dirs: Vec<&Path> = root
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(repo))
+            .collect();
+        dirs.reverse();
+        for dir in dirs {
+            let up = format!("{}/", relative(dir, &root));
+            for name in IGNORE_FILES {
+                if let Some(ignore) = Ignore::from_file(&dir.join(name)) {
+                    self.scopes.push(Scope {
+                        ignore,
+                        dir: String::new(),
+                        up: up.clone(),
+                    });
+                }
+            }
+        }
+        if repo != root {
+            self.repo_root = format!("{}/", relative(repo, &root));
+        }
+        Ok(())
+    }
+
+    fn visit(&mut self, dir: &Path) -> io::Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        let depth = self.scopes.len();
+        if !self.filter.no_ignore {
+            let relative = relative(self.root, dir);
+            for name in IGNORE_FILES {
+                if let Some(ignore) = Ignore::from_file(&dir.join(name)) {
+                    self.scopes.push(Scope {
+                        ignore,
+                        dir: if relative.is_empty() {
+                            relative.clone()
+                        } else {
+                            format!("{}/", relative)
+                        },
+                        up: String::new(),
+                    });
+                }
+            }
+        }
+
+        for entry in entries {
+            let path = entry.path();
+            // 不跟随指向目录的符号链接，避免死循环
+            let is_dir = entry.file_type()?.is_dir();
+            let relative = relative(self.root, &path);
+            if self.skipped(&entry.file_name(), &relative, is_dir)
+                || !self.filter.accepts(&relative, is_dir)
+            {
+                continue;
+            }
+            if is_dir {
+                // 某个子目录读不了时不影响其他目录
+                if let Err(e) = self.visit(&path) {
+                    eprintln!("{}: {}", path.display(), e);
+                }
+            } else if path.is_file() {
+                self.files.push(path);
+            }
+        }
+
+        self.scopes.truncate(depth);
+        Ok(())
+    }
+
+    /// 是否是隐藏文件，或者被忽略文件排除了
+    fn skipped(&self, name: &OsStr, relative: &str, is_dir: bool) -> bool {
+        if !self.filter.hidden && name.to_string_lossy().starts_with('.') {
+            return true;
+        }
+        if self.filter.no_ignore {
+            return false;
+        }
+        // git 自己的目录总是跳过
+        if is_dir && name == ".git" {
+            return true;
+        }
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.matched(relative, is_dir))
+            .or_else(|| {
+                let path = format!("{}{}", self.repo_root, relative);
+                self.filter.global.matched(&path, is_dir)
+            })
+            .unwrap_or(false)
+    }
+}
+
+/// 相对于 `root` 的路径，统一用 `/` 分隔
+pub fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn walk_with_filters() {
+        let root = env::temp_dir().join(format!("minigrep-walk-{}", std::process::id()));
+        for file in [
+            "src/main.rs",
+            "src/bin/a.rs",
+            "README.md",
+            "target/debug/b.rs",
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let filter = Filter {
+            include: vec![Glob::new("*.rs").unwrap()],
+            exclude: vec![Glob::new("target/").unwrap()],
+            ..Default::default()
+        };
+
+        let found: Vec<String> = files(&root, &filter)
+            .unwrap()
+            .iter()
+            .map(|path| relative(&root, path))
+            .collect();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(vec!["src/bin/a.rs", "src/main.rs"], found);
+    }
+
+    fn walk(root: &Path, filter: &Filter) -> Vec<String> {
+        files(root, filter)
+            .unwrap()
+            .iter()
+            .map(|path| relative(root, path))
+            .collect()
+    }
+
+    #[test]
+    fn walk_with_ignore_files() {
+        let root = env::temp_dir().join(format!("minigrep-ignore-{}", std::process::id()));
+        for (file, contents) in [
+            (".git/config", ""),
+            (".gitignore", "/target\n*.log\n!keep.log\nvendor/\n"),
+            (".hidden.rs", ""),
+            ("a.log", ""),
+            ("keep.log", ""),
+            ("target/debug/b.rs", ""),
+            ("src/.ignore", "generated.rs\n"),
+            ("src/generated.rs", ""),
+            ("src/main.rs", ""),
+            ("src/target/c.rs", ""),
+            ("src/vendor/d.rs", ""),
+            ("src/nested/.gitignore", "!*.log\n"),
+            ("src/nested/e.log", ""),
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let found = walk(&root, &Filter::default());
+        let hidden = walk(
+            &root,
+            &Filter {
+                hidden: true,
+                ..Default::default()
+            },
+        );
+        let everything = walk(
+            &root,
+            &Filter {
+                no_ignore: true,
+                ..Default::default()
+            },
+        );
+        // 从子目录开始遍历时，仓库根目录中的 `/target`、`vendor/` 规则仍然生效
+        let sub = walk(&root.join("src"), &Filter::default());
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            vec![
+                "keep.log",
+                "src/main.rs",
+                "src/nested/e.log",
+                "src/target/c.rs"
+            ],
+            found
+        );
+        assert_eq!(
+            vec![
+                ".gitignore",
+                ".hidden.rs",
+                "keep.log",
+                "src/.ignore",
+                "src/main.rs",
+                "src/nested/.gitignore",
+                "src/nested/e.log",
+                "src/target/c.rs"
+            ],
+            hidden
+        );
+        assert_eq!(8, everything.len());
+        assert_eq!(vec!["main.rs", "nested/e.log", "target/c.rs"], sub);
+    }
+}
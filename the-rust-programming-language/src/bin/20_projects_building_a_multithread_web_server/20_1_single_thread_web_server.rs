@@ -1,6 +1,16 @@
+// 和 m-web-server 共用的模块，这里只用到了其中的一部分
+mod connection;
+mod http;
+mod middleware;
+mod router;
+mod shutdown;
+
+use crate::connection::KeepAlive;
+use crate::http::Response;
+use crate::router::Router;
+use crate::shutdown::Connections;
 use std::fs;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::net::TcpListener;
 
 /// cargo r --bin s-web-server
 ///
@@ -20,30 +30,15 @@ use std::net::{TcpListener, TcpStream};
 ///
 fn main() {
     let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
+    let mut router = Router::new();
+    router.get("/", |_| match fs::read_to_string("hello.html") {
+        Ok(contents) => Response::html(200, contents),
+        Err(_) => Response::text(500, "Can't read file: hello.html\n"),
+    });
+    let connections = Connections::new();
     for stream in listener.incoming() {
         let stream = stream.unwrap();
-        handle_connection(stream);
+        // 只有一个线程，保持连接会让其他客户端一直等待
+        connection::serve(stream, &router, KeepAlive::disabled(), &connections);
     }
 }
-fn handle_connection(mut stream: TcpStream) {
-    let mut buffer = [0; 1024];
-    stream.read(&mut buffer).unwrap();
-
-    let get = b"GET / HTTP/1.1\r\n";
-    let (status_line, filename) = if buffer.starts_with(get) {
-        ("HTTP/1.1 200 OK", "hello.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND", "404.html")
-    };
-
-    let contents = fs::read_to_string(filename).unwrap();
-    let response = format!(
-        "{}\r\nContent-Length: {}\r\n\r\n{}",
-        status_line,
-        contents.len(),
-        contents
-    );
-
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
-}
@@ -1,62 +1,192 @@
+mod connection;
+mod http;
+mod middleware;
+mod router;
+mod shutdown;
+mod static_files;
 mod thread_pool;
 
-use crate::thread_pool::ThreadPool;
-use std::io::{BufRead, BufReader, Write};
+use crate::connection::{KeepAlive, Pending};
+use crate::http::{Request, Response};
+use crate::middleware::{constant_time_eq, AccessLog, BasicAuth, Chain, Gzip, Timing};
+use crate::router::Router;
+use crate::shutdown::Connections;
+use crate::static_files::StaticFiles;
+use crate::thread_pool::{Metrics, Monitor, Rejection, ThreadPool};
 use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
 use std::time::Duration;
-use std::{fs, thread};
+use std::{env, fs, process, thread};
 
+/// 收到关闭信号后，最多等待正在处理的请求这么久
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// 一个请求就能占住一个 worker，不限制的话几个请求就能让服务器不再响应
+const MAX_SLEEP: u64 = 10;
+
+///
+/// cargo r --bin m-web-server [-- <static-root>]
+///
+/// `/static/` 下的请求由 `static-root` 目录(默认是 `assets`)中的文件回复
+///
+/// `/metrics` 返回线程池的状态(线程数、正在执行和排队的任务数等)
 ///
-/// cargo r --bin m-web-server
+/// 线程池的队列满了时，新的连接直接收到 `503 Service Unavailable`
 ///
+/// 设置了环境变量 `ADMIN_PASSWORD` 时，可以用用户名 `admin` 访问需要认证的 `/admin`
+///
+/// 收到 SIGINT(Ctrl+C) 或 SIGTERM 后不再接受新连接，等待正在处理的请求完成后退出，
+/// 再收到一次信号时立即退出
 fn main() {
     let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(4);
+    // 持久连接会一直占着 worker，所以连接多的时候要能多开一些线程
+    let mut pool = ThreadPool::builder()
+        .min_threads(4)
+        .max_threads(32)
+        .keep_alive(Duration::from_secs(30))
+        .thread_name("web")
+        // 连接太多时不再排队，直接回复 503，免得排队的连接越来越多把内存耗光
+        .queue_capacity(64)
+        .rejection(Rejection::Reject)
+        .verbose(true)
+        .build();
+    // 处理函数 panic 时只会关闭这一个连接，worker 会继续处理别的连接
+    pool.set_panic_hook(|id, message| {
+        println!("Worker {id} panicked while serving a connection: {message}");
+    });
+    let static_root = env::args().nth(1).unwrap_or_else(|| String::from("assets"));
+    let router = Arc::new(routes(StaticFiles::new(static_root), pool.monitor()));
+    let connections = Arc::new(Connections::new());
+    handle_signals(&listener, Arc::clone(&connections));
+
     for stream in listener.incoming() {
-        let stream = stream.expect("connection wasn't established...");
-        pool.execute(|| {
-            handle_connection(stream);
+        if connections.is_stopping() {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                println!("connection wasn't established: {e}");
+                continue;
+            }
+        };
+        let router = Arc::clone(&router);
+        let connections = Arc::clone(&connections);
+        // 任务被拒绝时 `pending` 会跟着任务一起被 drop，回复 503
+        let pending = Pending::new(stream);
+        let job = pool.execute(move || {
+            let stream = pending.take();
+            connection::serve(stream, &router, KeepAlive::default(), &connections);
         });
+        if let Err(e) = job {
+            println!("Failed to handle connection: {e}");
+        }
     }
-}
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    // 只需要读取第一行，判断具体的 HTTP METHOD即可
-    let request_line = match buf_reader.lines().next() {
-        Some(next) => match next {
-            Ok(req) => req,
-            Err(_) => String::from("GET /error HTTP/1.1"),
-        },
-        None => String::from("GET /error HTTP/1.1"),
-    };
 
-    let (response_line, content_file) = match &request_line[..] {
-        "GET / HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(1));
-            ("HTTP/1.1 200 OK", "hello.html")
-        }
-        "GET /sleep HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(5));
-            ("HTTP/1.1 200 OK", "hello.html")
+    println!("Waiting for in-flight requests...");
+    let summary = connections.drain(DRAIN_TIMEOUT);
+    // 标记线程池正在关闭并唤醒所有等待任务的 worker，然后 join 它们，队列中剩下的任务会先执行完
+    pool.shutdown();
+    println!("Shutdown complete: {summary}");
+}
+fn handle_signals(listener: &TcpListener, connections: Arc<Connections>) {
+    let addr = listener.local_addr().unwrap();
+    ctrlc::set_handler(move || {
+        if connections.is_stopping() {
+            println!("Forced shutdown: {}", connections.summary());
+            process::exit(130);
         }
-        _ => {
+        println!("Shutting down, no longer accepting connections...");
+        connections.stop();
+        // accept 会一直阻塞，连接一下自己把它唤醒
+        let _ = TcpStream::connect(addr);
+    })
+    .expect("failed to set signal handler");
+}
+fn routes(static_files: StaticFiles, monitor: Monitor) -> Router {
+    let mut router = Router::new();
+    router
+        .get("/", |_| {
+            thread::sleep(Duration::from_secs(1));
+            html_file(200, "hello.html")
+        })
+        // 可以用 `/sleep/3` 或者 `/sleep?secs=3` 指定等待的秒数，最多 MAX_SLEEP 秒
+        .get("/sleep", sleep)
+        .get("/sleep/:secs", sleep)
+        .get("/static/*path", static_files.handler("path"))
+        .get("/metrics", move |_| metrics_page(monitor.metrics()))
+        .not_found(|_| {
             thread::sleep(Duration::from_secs(7));
-            ("HTTP/1.1 404 NOT FOUND", "404.html")
-        }
-    };
-
+            html_file(404, "404.html")
+        })
+        .wrap(AccessLog::stdout())
+        .wrap(Timing)
+        .wrap(Gzip::default());
+    if let Ok(password) = env::var("ADMIN_PASSWORD") {
+        let admin = Chain::new()
+            .with(BasicAuth::new("admin", move |user, input| {
+                user == "admin" && constant_time_eq(input, &password)
+            }))
+            .handler(|_| html_file(200, "hello.html"));
+        router.get("/admin", admin);
+    }
+    router
+}
+/// 线程池的状态，用 Prometheus 的文本格式输出
+fn metrics_page(metrics: Metrics) -> Response {
+    let rows = [
+        ("threads", "gauge", "Worker threads alive", metrics.threads),
+        ("active", "gauge", "Workers running a job", metrics.active),
+        ("idle", "gauge", "Workers waiting for a job", metrics.idle),
+        (
+            "queued",
+            "gauge",
+            "Jobs waiting in the queue",
+            metrics.queued,
+        ),
+        (
+            "completed_total",
+            "counter",
+            "Jobs finished",
+            metrics.completed,
+        ),
+        (
+            "panicked_total",
+            "counter",
+            "Jobs that panicked",
+            metrics.panicked,
+        ),
+        (
+            "rejected_total",
+            "counter",
+            "Jobs rejected because the queue was full",
+            metrics.rejected,
+        ),
+    ];
+    let mut body = String::new();
+    for (name, kind, help, value) in rows {
+        body.push_str(&format!(
+            "# HELP thread_pool_{name} {help}\n\
+             # TYPE thread_pool_{name} {kind}\n\
+             thread_pool_{name} {value}\n"
+        ));
+    }
+    Response::text(200, body)
+}
+fn sleep(request: &Request) -> Response {
+    let secs = request
+        .param("secs")
+        .map(str::to_string)
+        .or_else(|| request.query_param("secs"))
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(5)
+        .min(MAX_SLEEP);
+    thread::sleep(Duration::from_secs(secs));
+    html_file(200, "hello.html")
+}
+fn html_file(status: u16, content_file: &str) -> Response {
     let contents = match fs::read_to_string(content_file) {
         Ok(contents) => contents,
         Err(_) => format!("Can't read file: {content_file}"),
     };
-    let response = format!(
-        "{}\r\nContent-Length: {}\r\n\r\n{}",
-        response_line,
-        contents.len(),
-        contents
-    );
-
-    if let Err(_) = stream.write_all(response.as_bytes()) {
-        println!("Failed to response");
-    }
+    Response::html(status, contents)
 }
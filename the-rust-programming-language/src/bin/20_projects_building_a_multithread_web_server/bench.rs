@@ -0,0 +1,135 @@
+mod thread_pool;
+
+use crate::thread_pool::{Scheduler, ThreadPool};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// 性能比较：书中的 Arc<Mutex<Receiver>> VS Scheduler::Shared VS Scheduler::WorkStealing
+///
+/// cargo r --release --bin thread-pool-bench
+///
+/// ## 目录
+/// - 每个任务只是给计数器加 1，测的几乎全是线程池本身的开销
+/// - 书中的实现每取一个任务都要抢同一把锁，`recv` 还会在拿着锁的时候睡眠
+/// - `Scheduler::Shared` 也是一把锁，但是 worker 在 Condvar 上等待，不拿着锁睡眠
+/// - `Scheduler::WorkStealing` 取任务不加锁，worker 从 injector 一次拿一批到自己的队列，
+///   自己的队列空了再去偷别人的
+/// - 只有一个核的时候看不出抢锁的代价，核数越多、提交任务的线程越多，差距才越明显
+/// - 一定要用 --release 运行，debug 模式下的结果没有参考价值
+fn main() {
+    let threads = thread::available_parallelism().map_or(4, |n| n.get());
+    println!("{} workers, best of {} runs\n", threads, RUNS);
+    println!(
+        "{:<24}{:>12}{:>12}{:>14}{:>9}",
+        "case", "channel", "Shared", "WorkStealing", "speedup"
+    );
+
+    for (producers, jobs) in [(1, 100_000), (1, 1_000_000), (4, 1_000_000)] {
+        let channel = best_of(|| {
+            let pool = ChannelPool::new(threads);
+            run(producers, jobs, move |job| pool.execute(job))
+        });
+        let shared = best_of(|| {
+            let pool = pool(threads, Scheduler::Shared);
+            run(producers, jobs, move |job| pool.execute(job).unwrap())
+        });
+        let stealing = best_of(|| {
+            let pool = pool(threads, Scheduler::WorkStealing);
+            run(producers, jobs, move |job| pool.execute(job).unwrap())
+        });
+        println!(
+            "{:<24}{:>10.2?}{:>12.2?}{:>14.2?}{:>8.1}x",
+            format!("{} jobs, {} producers", jobs, producers),
+            channel,
+            shared,
+            stealing,
+            channel.as_secs_f64() / stealing.as_secs_f64()
+        );
+    }
+}
+
+const RUNS: usize = 5;
+
+fn pool(threads: usize, scheduler: Scheduler) -> ThreadPool {
+    ThreadPool::builder()
+        .min_threads(threads)
+        .max_threads(threads)
+        .scheduler(scheduler)
+        .build()
+}
+
+/// 从 `producers` 个线程一共提交 `jobs` 个任务，线程池被 drop 时等待所有任务完成
+fn run<F>(producers: usize, jobs: usize, execute: F) -> Duration
+where
+    F: Fn(Box<dyn FnOnce() + Send>) + Sync,
+{
+    let counter = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..producers {
+            let counter = &counter;
+            let execute = &execute;
+            scope.spawn(move || {
+                for _ in 0..jobs / producers {
+                    let counter = Arc::clone(counter);
+                    execute(Box::new(move || {
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    }));
+                }
+            });
+        }
+    });
+    drop(execute);
+    let elapsed = start.elapsed();
+    assert_eq!(jobs, counter.load(Ordering::Relaxed));
+    elapsed
+}
+
+fn best_of(f: impl Fn() -> Duration) -> Duration {
+    (0..RUNS).map(|_| f()).min().unwrap()
+}
+
+/// 书中最初的线程池: 所有 worker 共用一个 `Arc<Mutex<Receiver>>`
+struct ChannelPool {
+    workers: Vec<JoinHandle<()>>,
+    sender: Option<Sender<Box<dyn FnOnce() + Send>>>,
+}
+
+impl ChannelPool {
+    fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Box<dyn FnOnce() + Send>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+        ChannelPool {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+        self.sender.as_ref().unwrap().send(job).unwrap();
+    }
+}
+
+impl Drop for ChannelPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
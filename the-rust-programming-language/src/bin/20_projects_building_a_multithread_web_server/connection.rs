@@ -0,0 +1,379 @@
+use crate::http::{Method, ParseError, Request, Response, Version};
+use crate::router::Router;
+use crate::shutdown::{Connections, Guard};
+use std::io::{self, BufReader, Read};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// 关闭连接时最多等待客户端多久
+const LINGER_TIMEOUT: Duration = Duration::from_millis(500);
+/// 回复 503 之后最多等待客户端多久，服务器正忙，不能让这些连接占用太多线程
+#[allow(dead_code)]
+const REJECT_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// 持久连接的设置
+#[derive(Debug, Clone, Copy)]
+pub struct KeepAlive {
+    /// 等待下一个请求的最长时间，超时后关闭连接
+    pub idle_timeout: Duration,
+    /// 一个连接上最多处理的请求数，之后回复 `Connection: close`
+    pub max_requests: usize,
+}
+
+impl KeepAlive {
+    /// 每个连接只处理一个请求
+    ///
+    /// 单线程的服务器在等待空闲连接时没法处理别的连接，应该关掉持久连接
+    #[allow(dead_code)]
+    pub fn disabled() -> Self {
+        KeepAlive {
+            idle_timeout: Duration::from_secs(5),
+            max_requests: 1,
+        }
+    }
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        KeepAlive {
+            idle_timeout: Duration::from_secs(5),
+            max_requests: 100,
+        }
+    }
+}
+
+/// 在一个连接上依次读取请求并回复，直到客户端要求关闭、空闲超时或者达到请求数上限
+///
+/// 客户端可以不等回复就发送多个请求(pipelining)，它们都在 `BufReader` 的缓冲区里，
+/// 这里按顺序一个一个处理，所以回复的顺序和请求的顺序一致
+///
+/// 连接会被记录到 `connections` 中，开始关闭之后不再处理新的请求
+///
+/// 返回处理了多少个请求
+pub fn serve(
+    stream: TcpStream,
+    router: &Router,
+    keep_alive: KeepAlive,
+    connections: &Connections,
+) -> usize {
+    let guard = match connections.register(&stream) {
+        Some(guard) => guard,
+        None => return 0,
+    };
+    let served = match serve_requests(&stream, router, keep_alive, &guard) {
+        Ok(served) => served,
+        Err((served, e)) => {
+            // 空闲超时和客户端主动断开都是正常情况
+            if !is_disconnect(&e) {
+                println!("Connection error: {e}");
+            }
+            served
+        }
+    };
+    drop(guard);
+    linger(&stream, LINGER_TIMEOUT);
+    served
+}
+
+/// 等待线程池处理的连接
+///
+/// 没有被处理就被丢掉时(线程池的队列满了、被挤出了队列或者线程池已经关闭)回复 `503`
+#[allow(dead_code)]
+pub struct Pending(Option<TcpStream>);
+
+impl Pending {
+    #[allow(dead_code)]
+    pub fn new(stream: TcpStream) -> Self {
+        Pending(Some(stream))
+    }
+
+    /// 开始处理这个连接
+    #[allow(dead_code)]
+    pub fn take(mut self) -> TcpStream {
+        self.0.take().unwrap()
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if let Some(stream) = self.0.take() {
+            unavailable(stream);
+        }
+    }
+}
+
+/// 不处理请求，直接回复 `503 Service Unavailable` 然后关闭连接
+///
+/// 一般在接受连接的线程中调用，不能阻塞: 回复很短，新连接的发送缓冲区一定放得下，
+/// 非阻塞地写进去就行了；读掉请求再关闭连接要等客户端，交给一个临时线程
+#[allow(dead_code)]
+fn unavailable(mut stream: TcpStream) {
+    if stream.set_nonblocking(true).is_err() {
+        return;
+    }
+    let _ = Response::text(503, "Server is busy, please try again later\n")
+        .header("Retry-After", "1")
+        .header("Connection", "close")
+        .write_to(&mut stream);
+    let _ = thread::Builder::new()
+        .name(String::from("reject"))
+        .spawn(move || {
+            if stream.set_nonblocking(false).is_ok() {
+                linger(&stream, REJECT_TIMEOUT);
+            }
+        });
+}
+
+/// 关闭连接之前先关闭写的一端，再读掉客户端已经发送的数据
+///
+/// 如果直接关闭还有未读数据的连接，内核会发送 RST，客户端可能还没读到最后的回复就报错了
+fn linger(mut stream: &TcpStream, timeout: Duration) {
+    if stream.shutdown(Shutdown::Write).is_err() {
+        return;
+    }
+    let deadline = Instant::now() + timeout;
+    let _ = stream.set_read_timeout(Some(timeout));
+    let mut buf = [0; 4096];
+    while Instant::now() < deadline {
+        match stream.read(&mut buf) {
+            Ok(n) if n > 0 => continue,
+            _ => break,
+        }
+    }
+}
+
+fn serve_requests(
+    stream: &TcpStream,
+    router: &Router,
+    keep_alive: KeepAlive,
+    guard: &Guard,
+) -> Result<usize, (usize, io::Error)> {
+    stream
+        .set_read_timeout(Some(keep_alive.idle_timeout))
+        .map_err(|e| (0, e))?;
+    let mut reader = BufReader::new(stream);
+    let mut writer = stream;
+    let mut served = 0;
+    loop {
+        let mut request = match Request::read_from(&mut reader) {
+            Ok(Some(mut request)) => {
+                request.remote_addr = stream.peer_addr().ok();
+                request
+            }
+            Ok(None) => return Ok(served),
+            Err(e) => {
+                let status = match e.status() {
+                    Some(status) => status,
+                    None => return Err((served, into_io_error(e))),
+                };
+                // 格式错误的请求之后的内容没法再解析了，回复之后直接关闭连接
+                Response::text(status, format!("{e}\n"))
+                    .header("Connection", "close")
+                    .write_to(&mut writer)
+                    .map_err(|e| (served, e))?;
+                return Ok(served);
+            }
+        };
+
+        guard.busy();
+        served += 1;
+        let mut response = router.handle(&mut request);
+        let persistent = wants_keep_alive(&request, &response)
+            && served < keep_alive.max_requests
+            && !guard.is_stopping();
+        if persistent {
+            if request.version == Version::Http10 {
+                response.headers.set("Connection", "keep-alive");
+            }
+            response.headers.set(
+                "Keep-Alive",
+                format!(
+                    "timeout={}, max={}",
+                    keep_alive.idle_timeout.as_secs(),
+                    keep_alive.max_requests - served
+                ),
+            );
+        } else {
+            response.headers.set("Connection", "close");
+        }
+
+        let written = if request.method == Method::Head {
+            response.write_head_to(&mut writer)
+        } else {
+            response.write_to(&mut writer)
+        };
+        written.map_err(|e| (served, e))?;
+        // 回复的过程中可能开始关闭了
+        if !guard.idle() || !persistent {
+            return Ok(served);
+        }
+    }
+}
+
+/// HTTP/1.1 默认是持久连接，除非有 `Connection: close`；HTTP/1.0 要有 `Connection: keep-alive`。
+/// 处理函数也可以在回复中加上 `Connection: close` 来关闭连接
+fn wants_keep_alive(request: &Request, response: &Response) -> bool {
+    if response.headers.has_token("Connection", "close") {
+        return false;
+    }
+    match request.version {
+        Version::Http11 => !request.headers.has_token("Connection", "close"),
+        Version::Http10 => request.headers.has_token("Connection", "keep-alive"),
+    }
+}
+
+fn into_io_error(e: ParseError) -> io::Error {
+    match e {
+        ParseError::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::UnexpectedEof, e),
+    }
+}
+
+fn is_disconnect(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    /// 启动一个只接受一个连接的服务器，返回客户端的连接和服务器处理的请求数
+    fn connect(keep_alive: KeepAlive) -> (TcpStream, thread::JoinHandle<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut router = Router::new();
+            router
+                .get("/bye", |_| {
+                    Response::text(200, "bye").header("Connection", "close")
+                })
+                .get("/:name", |req| {
+                    Response::text(200, req.param("name").unwrap().to_string())
+                });
+            let (stream, _) = listener.accept().unwrap();
+            serve(stream, &router, keep_alive, &Connections::new())
+        });
+        (TcpStream::connect(addr).unwrap(), server)
+    }
+
+    /// 读取直到服务器关闭连接
+    fn read_all(mut client: TcpStream) -> String {
+        let mut out = String::new();
+        client.read_to_string(&mut out).unwrap();
+        out
+    }
+
+    fn bodies(out: &str) -> Vec<&str> {
+        out.split("HTTP/1.1 ")
+            .skip(1)
+            .map(|response| response.split("\r\n\r\n").nth(1).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn pipelined_requests_in_order() {
+        let (mut client, server) = connect(KeepAlive::default());
+        client
+            .write_all(
+                b"GET /a HTTP/1.1\r\nHost: x\r\n\r\n\
+                  GET /b HTTP/1.1\r\nHost: x\r\n\r\n\
+                  HEAD /c HTTP/1.1\r\nHost: x\r\n\r\n\
+                  GET /d HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n\
+                  GET /never HTTP/1.1\r\nHost: x\r\n\r\n",
+            )
+            .unwrap();
+        let out = read_all(client);
+        assert_eq!(vec!["a", "b", "", "d"], bodies(&out));
+        assert_eq!(3, out.matches("Keep-Alive: timeout=5, max=").count());
+        assert!(out.ends_with("Connection: close\r\nContent-Length: 1\r\n\r\nd"));
+        assert_eq!(4, server.join().unwrap());
+    }
+
+    #[test]
+    fn close_after_limits() {
+        // 达到请求数上限
+        let keep_alive = KeepAlive {
+            idle_timeout: Duration::from_secs(5),
+            max_requests: 2,
+        };
+        let (mut client, server) = connect(keep_alive);
+        client
+            .write_all(b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nGET /b HTTP/1.1\r\nHost: x\r\n\r\nGET /c HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        assert_eq!(vec!["a", "b"], bodies(&read_all(client)));
+        assert_eq!(2, server.join().unwrap());
+
+        // 处理函数要求关闭
+        let (mut client, server) = connect(KeepAlive::default());
+        client
+            .write_all(b"GET /bye HTTP/1.1\r\nHost: x\r\n\r\nGET /a HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        assert_eq!(vec!["bye"], bodies(&read_all(client)));
+        assert_eq!(1, server.join().unwrap());
+
+        // HTTP/1.0 默认不是持久连接
+        let (mut client, server) = connect(KeepAlive::default());
+        client
+            .write_all(b"GET /a HTTP/1.0\r\n\r\nGET /b HTTP/1.0\r\n\r\n")
+            .unwrap();
+        assert_eq!(vec!["a"], bodies(&read_all(client)));
+        assert_eq!(1, server.join().unwrap());
+
+        // 空闲超时
+        let keep_alive = KeepAlive {
+            idle_timeout: Duration::from_millis(100),
+            max_requests: 100,
+        };
+        let (mut client, server) = connect(keep_alive);
+        let start = Instant::now();
+        client
+            .write_all(b"GET /a HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+        let out = read_all(client);
+        assert!(out.contains("Connection: keep-alive\r\n"));
+        assert_eq!(vec!["a"], bodies(&out));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(1, server.join().unwrap());
+    }
+
+    #[test]
+    fn dropped_pending_connection_gets_503() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        let pending = Pending::new(listener.accept().unwrap().0);
+        // 客户端一直不关闭连接也不会阻塞调用方
+        let start = Instant::now();
+        drop(pending);
+        assert!(start.elapsed() < REJECT_TIMEOUT);
+        let out = read_all(client);
+        assert!(out.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(out.contains("Retry-After: 1\r\n"));
+    }
+
+    #[test]
+    fn malformed_request_closes_connection() {
+        let (mut client, server) = connect(KeepAlive::default());
+        client
+            .write_all(b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nGARBAGE\r\n\r\nGET /b HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let out = read_all(client);
+        assert_eq!(2, bodies(&out).len());
+        assert!(out.contains("HTTP/1.1 400 Bad Request\r\n"));
+        assert_eq!(1, server.join().unwrap());
+    }
+}
@@ -0,0 +1,699 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+/// 请求行和每个头部行的最大长度
+const MAX_LINE: u64 = 8 * 1024;
+/// 头部的最大数量
+const MAX_HEADERS: usize = 100;
+/// 请求体的最大长度
+const MAX_BODY: usize = 8 * 1024 * 1024;
+
+/// 解析请求时的错误，除了 `Io` 之外都应该回复对应的状态码然后关闭连接
+#[derive(thiserror::Error, Debug)]
+pub enum ParseError {
+    #[error("malformed request line '{0}'")]
+    RequestLine(String),
+    #[error("unsupported method '{0}'")]
+    Method(String),
+    #[error("unsupported version '{0}'")]
+    Version(String),
+    #[error("malformed request target '{0}'")]
+    Target(String),
+    #[error("malformed header '{0}'")]
+    Header(String),
+    #[error("too many headers")]
+    TooManyHeaders,
+    #[error("line is longer than {MAX_LINE} bytes")]
+    LineTooLong,
+    #[error("request is not valid UTF-8")]
+    Utf8,
+    #[error("missing Host header")]
+    MissingHost,
+    #[error("invalid Content-Length '{0}'")]
+    ContentLength(String),
+    #[error("both Transfer-Encoding and Content-Length are present")]
+    AmbiguousLength,
+    #[error("unsupported Transfer-Encoding '{0}'")]
+    TransferEncoding(String),
+    #[error("malformed chunk '{0}'")]
+    Chunk(String),
+    #[error("body is larger than {MAX_BODY} bytes")]
+    BodyTooLarge,
+    #[error("connection closed in the middle of a request")]
+    UnexpectedEof,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl ParseError {
+    /// 回复给客户端的状态码，读写失败时没法再回复，返回 `None`
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ParseError::Io(_) | ParseError::UnexpectedEof => None,
+            ParseError::Method(_) | ParseError::TransferEncoding(_) => Some(501),
+            ParseError::Version(_) => Some(505),
+            ParseError::TooManyHeaders | ParseError::LineTooLong => Some(431),
+            ParseError::BodyTooLarge => Some(413),
+            _ => Some(400),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = ParseError;
+
+    /// 方法名区分大小写
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "PATCH" => Method::Patch,
+            "OPTIONS" => Method::Options,
+            _ => return Err(ParseError::Method(s.to_string())),
+        })
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    #[allow(dead_code)]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+/// 按出现顺序保存的头部，名字不区分大小写
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// 第一个名为 `name` 的头部
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// 追加一个头部，不会覆盖已有的同名头部
+    pub fn append(&mut self, name: &str, value: impl Into<String>) {
+        self.entries.push((name.to_string(), value.into()));
+    }
+
+    /// 设置头部，会先删掉所有同名的头部
+    pub fn set(&mut self, name: &str, value: impl Into<String>) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    /// 逗号分隔的列表类头部(例如 `Connection`)中是否有 `token`，不区分大小写
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+/// 解析好的 HTTP/1.x 请求
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: Method,
+    /// 请求行中原始的请求目标，例如 `/users/a%20b?id=1`
+    pub target: String,
+    /// 已经做过百分号解码的路径，总是以 `/` 开头
+    pub path: String,
+    /// `?` 之后的原始查询字符串，没有解码
+    pub query: Option<String>,
+    pub version: Version,
+    pub headers: Headers,
+    /// `Content-Length` 或者 chunked 编码的请求体，chunked 的请求体已经拼接好
+    pub body: Vec<u8>,
+    /// 路由匹配到的路径参数，由 `Router` 填写
+    pub params: HashMap<String, String>,
+    /// 客户端的地址，由读取请求的连接填写
+    pub remote_addr: Option<SocketAddr>,
+}
+
+impl Request {
+    /// 从连接中读取一个完整的请求
+    ///
+    /// 在请求开始之前连接就被关闭时返回 `Ok(None)`
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Option<Request>, ParseError> {
+        // RFC 9112 建议忽略请求行之前的空行
+        let request_line = loop {
+            match read_line(reader)? {
+                None => return Ok(None),
+                Some(line) if line.is_empty() => continue,
+                Some(line) => break line,
+            }
+        };
+        let mut parts = request_line.split(' ');
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version))
+                if parts.next().is_none() && !method.is_empty() && !target.is_empty() =>
+            {
+                (method, target, version)
+            }
+            _ => return Err(ParseError::RequestLine(request_line)),
+        };
+        let version = match version {
+            "HTTP/1.1" => Version::Http11,
+            "HTTP/1.0" => Version::Http10,
+            _ if version.starts_with("HTTP/") => {
+                return Err(ParseError::Version(version.to_string()))
+            }
+            _ => return Err(ParseError::RequestLine(request_line)),
+        };
+        let method = method.parse()?;
+        let (path, query) = parse_target(target)?;
+
+        let headers = read_headers(reader)?;
+        if version == Version::Http11 && !headers.contains("Host") {
+            return Err(ParseError::MissingHost);
+        }
+        let body = read_body(reader, &headers)?;
+        Ok(Some(Request {
+            method,
+            target: target.to_string(),
+            path,
+            query,
+            version,
+            headers,
+            body,
+            params: HashMap::new(),
+            remote_addr: None,
+        }))
+    }
+
+    /// 路径参数，例如 `/users/:id` 中的 `id`
+    #[allow(dead_code)]
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    /// 解码之后的查询参数，`+` 表示空格，没法解码的参数会被跳过
+    #[allow(dead_code)]
+    pub fn query_params(&self) -> Vec<(String, String)> {
+        let query = match &self.query {
+            Some(query) => query,
+            None => return Vec::new(),
+        };
+        query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .filter_map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                let key = percent_decode(&key.replace('+', " "))?;
+                let value = percent_decode(&value.replace('+', " "))?;
+                Some((key, value))
+            })
+            .collect()
+    }
+
+    /// 第一个名为 `name` 的查询参数
+    #[allow(dead_code)]
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query_params()
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// 读取一行并去掉结尾的 `\r\n`(也接受单独的 `\n`)，连接关闭时返回 `None`
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    reader
+        .by_ref()
+        .take(MAX_LINE)
+        .read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(if line.len() as u64 + 1 >= MAX_LINE {
+            ParseError::LineTooLong
+        } else {
+            ParseError::UnexpectedEof
+        });
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| ParseError::Utf8)
+}
+
+/// 读到空行为止的头部(也用于 chunked 编码结尾的 trailer)
+fn read_headers<R: BufRead>(reader: &mut R) -> Result<Headers, ParseError> {
+    let mut headers = Headers::default();
+    loop {
+        let line = read_line(reader)?.ok_or(ParseError::UnexpectedEof)?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if headers.entries.len() == MAX_HEADERS {
+            return Err(ParseError::TooManyHeaders);
+        }
+        // 冒号之前不能有空白，已经废弃的多行头部(以空白开头的续行)也不支持
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) if is_token(name) => (name, value.trim_matches([' ', '\t'])),
+            _ => return Err(ParseError::Header(line)),
+        };
+        headers.append(name, value);
+    }
+}
+
+fn read_body<R: BufRead>(reader: &mut R, headers: &Headers) -> Result<Vec<u8>, ParseError> {
+    if let Some(encoding) = headers.get("Transfer-Encoding") {
+        if headers.contains("Content-Length") {
+            return Err(ParseError::AmbiguousLength);
+        }
+        // 只支持 chunked，而且它必须是最后一个编码
+        let codings: Vec<_> = headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        if codings.len() != 1 || !codings[0].eq_ignore_ascii_case("chunked") {
+            return Err(ParseError::TransferEncoding(encoding.to_string()));
+        }
+        return read_chunked(reader);
+    }
+
+    let mut length = None;
+    for value in headers.get_all("Content-Length") {
+        let parsed = match value.parse::<usize>() {
+            Ok(parsed) if value.bytes().all(|b| b.is_ascii_digit()) => parsed,
+            _ => return Err(ParseError::ContentLength(value.to_string())),
+        };
+        // 多个 Content-Length 的值必须相同
+        if length.is_some_and(|length| length != parsed) {
+            return Err(ParseError::ContentLength(value.to_string()));
+        }
+        length = Some(parsed);
+    }
+    let length = length.unwrap_or(0);
+    if length > MAX_BODY {
+        return Err(ParseError::BodyTooLarge);
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(eof_to_parse_error)?;
+    Ok(body)
+}
+
+/// ```
+/// chunk-size [ ; chunk-ext ] CRLF
+/// chunk-data CRLF
+/// ...
+/// 0 CRLF
+/// [ trailer ] CRLF
+/// ```
+fn read_chunked<R: BufRead>(reader: &mut R) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader)?.ok_or(ParseError::UnexpectedEof)?;
+        let hex = line.split(';').next().unwrap_or_default().trim();
+        let size = match usize::from_str_radix(hex, 16) {
+            Ok(size) if hex.bytes().all(|b| b.is_ascii_hexdigit()) => size,
+            _ => return Err(ParseError::Chunk(line)),
+        };
+        if size == 0 {
+            // trailer 中的头部直接丢掉
+            read_headers(reader)?;
+            return Ok(body);
+        }
+        if body.len() + size > MAX_BODY {
+            return Err(ParseError::BodyTooLarge);
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader
+            .read_exact(&mut body[start..])
+            .map_err(eof_to_parse_error)?;
+        match read_line(reader)? {
+            Some(line) if line.is_empty() => {}
+            Some(line) => return Err(ParseError::Chunk(line)),
+            None => return Err(ParseError::UnexpectedEof),
+        }
+    }
+}
+
+fn eof_to_parse_error(err: io::Error) -> ParseError {
+    if err.kind() == io::ErrorKind::UnexpectedEof {
+        ParseError::UnexpectedEof
+    } else {
+        ParseError::Io(err)
+    }
+}
+
+/// 只支持 origin-form(`/path?query`)和 absolute-form(`http://host/path?query`)
+fn parse_target(target: &str) -> Result<(String, Option<String>), ParseError> {
+    let origin = match target.strip_prefix("http://") {
+        Some(rest) => match rest.find('/') {
+            Some(slash) => &rest[slash..],
+            None => "/",
+        },
+        None => target,
+    };
+    if !origin.starts_with('/') || origin.contains('#') {
+        return Err(ParseError::Target(target.to_string()));
+    }
+    let (path, query) = match origin.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (origin, None),
+    };
+    let path = percent_decode(path).ok_or_else(|| ParseError::Target(target.to_string()))?;
+    Ok((path, query))
+}
+
+/// 百分号解码，编码不完整或者解码后不是 UTF-8 时返回 `None`
+pub fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// RFC 9110 中的 token，头部名字只能由这些字符组成
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// 状态码对应的原因短语
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        206 => "Partial Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Content Too Large",
+        416 => "Range Not Satisfiable",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+/// 要回复给客户端的响应
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Response {
+            status,
+            headers: Headers::default(),
+            body: Vec::new(),
+        }
+    }
+
+    /// `text/html` 类型的响应
+    pub fn html(status: u16, contents: impl Into<Vec<u8>>) -> Self {
+        Self::new(status)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(contents)
+    }
+
+    /// `text/plain` 类型的响应，一般用于错误信息
+    pub fn text(status: u16, contents: impl Into<Vec<u8>>) -> Self {
+        Self::new(status)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(contents)
+    }
+
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.set(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// 写出状态行、头部和响应体，`Content-Length` 总是根据响应体重新计算
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.write(writer, true)
+    }
+
+    /// 回复 `HEAD` 请求，和 `write_to` 一样但是不写响应体
+    pub fn write_head_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.write(writer, false)
+    }
+
+    fn write<W: Write>(&self, writer: &mut W, with_body: bool) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in self.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+        // 204 和 304 没有响应体，也不需要 Content-Length
+        if !matches!(self.status, 204 | 304) {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+        writer.write_all(head.as_bytes())?;
+        if with_body {
+            writer.write_all(&self.body)?;
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Option<Request>, ParseError> {
+        Request::read_from(&mut raw.as_bytes())
+    }
+
+    #[test]
+    fn parse_requests() {
+        let request = parse(
+            "GET /users/a%20b?id=1&name=x+y HTTP/1.1\r\n\
+             Host: localhost:7878\r\n\
+             Accept:  text/html \r\n\
+             X-Tag: a\r\n\
+             x-tag: b\r\n\
+             \r\n",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(Method::Get, request.method);
+        assert_eq!("/users/a b", request.path);
+        assert_eq!(Some("id=1&name=x+y"), request.query.as_deref());
+        assert_eq!(Some("x y".to_string()), request.query_param("name"));
+        assert_eq!(Version::Http11, request.version);
+        assert_eq!(Some("text/html"), request.headers.get("accept"));
+        assert_eq!(
+            vec!["a", "b"],
+            request.headers.get_all("X-TAG").collect::<Vec<_>>()
+        );
+        assert!(request.body.is_empty());
+
+        let request = parse("POST http://localhost/form HTTP/1.0\nContent-Length: 5\n\nhello")
+            .unwrap()
+            .unwrap();
+        assert_eq!("/form", request.path);
+        assert_eq!(b"hello", &request.body[..]);
+
+        let raw = "POST /upload HTTP/1.1\r\n\
+                   Host: localhost\r\n\
+                   Transfer-Encoding: chunked\r\n\
+                   \r\n\
+                   5;ext=1\r\nhello\r\n\
+                   7\r\n, world\r\n\
+                   0\r\n\
+                   Trailer: ignored\r\n\
+                   \r\n\
+                   GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let mut reader = raw.as_bytes();
+        let request = Request::read_from(&mut reader).unwrap().unwrap();
+        assert_eq!(b"hello, world", &request.body[..]);
+        // 同一个连接上的下一个请求不受影响
+        let next = Request::read_from(&mut reader).unwrap().unwrap();
+        assert_eq!("/", next.path);
+        assert_eq!(None, Request::read_from(&mut reader).unwrap());
+    }
+
+    #[test]
+    fn malformed_requests() {
+        let status = |raw: &str| parse(raw).unwrap_err().status();
+        assert_eq!(Some(400), status("GET /\r\n\r\n"));
+        assert_eq!(Some(400), status("GET  / HTTP/1.1\r\nHost: a\r\n\r\n"));
+        assert_eq!(Some(400), status("GET / HTTP/1.1\r\n\r\n"));
+        assert_eq!(
+            Some(400),
+            status("GET index.html HTTP/1.1\r\nHost: a\r\n\r\n")
+        );
+        assert_eq!(Some(400), status("GET /%zz HTTP/1.1\r\nHost: a\r\n\r\n"));
+        assert_eq!(Some(400), status("GET / HTTP/1.1\r\nHost : a\r\n\r\n"));
+        assert_eq!(
+            Some(400),
+            status("GET / HTTP/1.1\r\nHost: a\r\n folded\r\n\r\n")
+        );
+        assert_eq!(
+            Some(400),
+            status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: -1\r\n\r\n")
+        );
+        assert_eq!(
+            Some(400),
+            status(
+                "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab"
+            )
+        );
+        assert_eq!(
+            Some(400),
+            status(
+                "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\
+                 Content-Length: 1\r\n\r\n0\r\n\r\n"
+            )
+        );
+        assert_eq!(
+            Some(400),
+            status("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\nxyz\r\n")
+        );
+        assert_eq!(Some(501), status("BREW / HTTP/1.1\r\nHost: a\r\n\r\n"));
+        assert_eq!(
+            Some(501),
+            status("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip\r\n\r\n")
+        );
+        assert_eq!(Some(505), status("GET / HTTP/2.0\r\nHost: a\r\n\r\n"));
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE as usize));
+        assert_eq!(Some(431), status(&long));
+        // 请求体不完整时连接已经断了，不需要回复
+        assert_eq!(
+            None,
+            status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\nabc")
+        );
+    }
+
+    #[test]
+    fn write_responses() {
+        let mut out = Vec::new();
+        Response::html(404, "<p>oops</p>")
+            .header("Content-Length", "999")
+            .write_to(&mut out)
+            .unwrap();
+        assert_eq!(
+            "HTTP/1.1 404 Not Found\r\n\
+             Content-Type: text/html; charset=utf-8\r\n\
+             Content-Length: 11\r\n\
+             \r\n\
+             <p>oops</p>",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        Response::text(200, "hello")
+            .write_head_to(&mut out)
+            .unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("Content-Length: 5\r\n\r\n"));
+        let mut out = Vec::new();
+        Response::new(304).write_to(&mut out).unwrap();
+        assert_eq!(
+            "HTTP/1.1 304 Not Modified\r\n\r\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -0,0 +1,487 @@
+use crate::http::{Request, Response};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// 包在处理函数外面的中间件，可以在调用 `next` 之前检查或者拦截请求，之后修改响应
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response;
+}
+
+/// 按顺序组合的中间件，先添加的在最外层
+///
+/// ```ignore
+/// let admin = Chain::new()
+///     .with(BasicAuth::new("admin", |user, password| user == "admin" && password == "secret"))
+///     .handler(|_| Response::text(200, "welcome"));
+/// router.get("/admin", admin);
+/// ```
+#[derive(Clone, Default)]
+pub struct Chain {
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[allow(dead_code)]
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.push(middleware);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn push(&mut self, middleware: impl Middleware + 'static) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /// 把中间件包在 `handler` 外面，得到一个新的处理函数，可以注册到路由中
+    #[allow(dead_code)]
+    pub fn handler<F>(self, handler: F) -> impl Fn(&Request) -> Response + Send + Sync
+    where
+        F: Fn(&Request) -> Response + Send + Sync,
+    {
+        move |request| self.run(request, &handler)
+    }
+
+    /// 依次经过所有中间件，最后交给 `handler`
+    pub fn run(&self, request: &Request, handler: &dyn Fn(&Request) -> Response) -> Response {
+        self.run_from(0, request, handler)
+    }
+
+    fn run_from(
+        &self,
+        index: usize,
+        request: &Request,
+        handler: &dyn Fn(&Request) -> Response,
+    ) -> Response {
+        match self.middleware.get(index) {
+            Some(middleware) => middleware.handle(request, &|request| {
+                self.run_from(index + 1, request, handler)
+            }),
+            None => handler(request),
+        }
+    }
+}
+
+/// 以 Common Log Format 记录每个请求:
+///
+/// ```text
+/// 127.0.0.1 - frank [10/Oct/2000:13:55:36 +0000] "GET /apache_pb.gif HTTP/1.0" 200 2326
+/// ```
+///
+/// 时间统一使用 UTC
+#[allow(dead_code)]
+pub struct AccessLog {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    /// 输出到标准输出
+    #[allow(dead_code)]
+    pub fn stdout() -> Self {
+        Self::to(io::stdout())
+    }
+
+    #[allow(dead_code)]
+    pub fn to(out: impl Write + Send + 'static) -> Self {
+        AccessLog {
+            out: Mutex::new(Box::new(out)),
+        }
+    }
+}
+
+impl Middleware for AccessLog {
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        let response = next(request);
+        let host = request
+            .remote_addr
+            .map_or_else(|| "-".to_string(), |addr| addr.ip().to_string());
+        let user = basic_credentials(request).map_or_else(|| "-".to_string(), |(user, _)| user);
+        let bytes = match response.body.len() {
+            0 => "-".to_string(),
+            len => len.to_string(),
+        };
+        let line = format!(
+            "{host} - {user} [{}] \"{} {} {}\" {} {bytes}\n",
+            clf_date(SystemTime::now()),
+            request.method,
+            request.target,
+            request.version.as_str(),
+            response.status,
+        );
+        // 日志写失败不影响回复
+        if let Ok(mut out) = self.out.lock() {
+            let _ = out.write_all(line.as_bytes());
+        }
+        response
+    }
+}
+
+/// `10/Oct/2000:13:55:36 +0000`
+#[allow(dead_code)]
+fn clf_date(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // 把 1970-01-01 之后的天数转成年月日，算法来自 Howard Hinnant 的 civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{day:02}/{}/{year}:{:02}:{:02}:{:02} +0000",
+        MONTHS[month as usize - 1],
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// 在响应中加上处理请求花费的时间:
+///
+/// ```text
+/// Server-Timing: app;dur=12.345
+/// ```
+#[allow(dead_code)]
+pub struct Timing;
+
+impl Middleware for Timing {
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        let start = Instant::now();
+        let mut response = next(request);
+        let millis = start.elapsed().as_secs_f64() * 1000.0;
+        response
+            .headers
+            .append("Server-Timing", format!("app;dur={millis:.3}"));
+        response
+    }
+}
+
+/// 客户端的 `Accept-Encoding` 支持 gzip 时压缩响应体
+///
+/// 太小的响应、已经压缩过的内容(图片、视频等)和部分内容(`206`)不压缩
+#[allow(dead_code)]
+pub struct Gzip {
+    /// 小于这个长度的响应体不压缩
+    pub min_size: usize,
+}
+
+impl Default for Gzip {
+    fn default() -> Self {
+        Gzip { min_size: 256 }
+    }
+}
+
+impl Middleware for Gzip {
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        let mut response = next(request);
+        // 不管有没有压缩，同一个地址的响应都和 Accept-Encoding 有关
+        response.headers.append("Vary", "Accept-Encoding");
+        if !accepts_gzip(request)
+            || response.status != 200
+            || response.body.len() < self.min_size
+            || response.headers.contains("Content-Encoding")
+            || !is_compressible(response.headers.get("Content-Type").unwrap_or_default())
+        {
+            return response;
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        let compressed = match encoder
+            .write_all(&response.body)
+            .and_then(|_| encoder.finish())
+        {
+            Ok(compressed) => compressed,
+            Err(_) => return response,
+        };
+        response.body = compressed;
+        response.headers.set("Content-Encoding", "gzip");
+        // 压缩之后内容变了，强 ETag 要变成弱 ETag，也不能再处理 Range
+        if let Some(etag) = response.headers.get("ETag") {
+            if !etag.starts_with("W/") {
+                let weak = format!("W/{etag}");
+                response.headers.set("ETag", weak);
+            }
+        }
+        response.headers.remove("Accept-Ranges");
+        response
+    }
+}
+
+/// `Accept-Encoding: gzip, deflate;q=0.5`，`q=0` 表示不接受，`*` 表示任意编码
+#[allow(dead_code)]
+fn accepts_gzip(request: &Request) -> bool {
+    request
+        .headers
+        .get_all("Accept-Encoding")
+        .flat_map(|value| value.split(','))
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let coding = parts.next()?;
+            let q = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(1.0, |q| q.parse().unwrap_or(0.0));
+            Some((coding, q))
+        })
+        .any(|(coding, q)| (coding.eq_ignore_ascii_case("gzip") || coding == "*") && q > 0.0)
+}
+
+#[allow(dead_code)]
+fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/json" | "application/xml" | "application/wasm" | "image/svg+xml"
+        )
+}
+
+/// HTTP Basic 认证，用户名和密码由 `check` 检查，不通过时回复 `401`
+#[allow(dead_code)]
+pub struct BasicAuth<F> {
+    realm: String,
+    check: F,
+}
+
+impl<F> BasicAuth<F>
+where
+    F: Fn(&str, &str) -> bool + Send + Sync,
+{
+    #[allow(dead_code)]
+    pub fn new(realm: &str, check: F) -> Self {
+        BasicAuth {
+            realm: realm.to_string(),
+            check,
+        }
+    }
+}
+
+impl<F> Middleware for BasicAuth<F>
+where
+    F: Fn(&str, &str) -> bool + Send + Sync,
+{
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        match basic_credentials(request) {
+            Some((user, password)) if (self.check)(&user, &password) => next(request),
+            _ => Response::text(401, "Unauthorized\n").header(
+                "WWW-Authenticate",
+                format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm),
+            ),
+        }
+    }
+}
+
+/// 比较密码时用，耗时和两个字符串在哪里不同无关，避免通过响应时间猜出密码
+#[allow(dead_code)]
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+/// `Authorization: Basic base64(user:password)` 中的用户名和密码
+#[allow(dead_code)]
+fn basic_credentials(request: &Request) -> Option<(String, String)> {
+    let value = request.headers.get("Authorization")?;
+    let (scheme, encoded) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(base64_decode(encoded.trim())?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// 标准的 base64 解码，结尾的 `=` 可以省略
+#[allow(dead_code)]
+fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        Some(match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        } as u32)
+    }
+
+    let encoded = encoded.trim_end_matches('=').as_bytes();
+    if encoded.len() % 4 == 1 {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    for chunk in encoded.chunks(4) {
+        let mut bits = 0;
+        for (i, &c) in chunk.iter().enumerate() {
+            bits |= value(c)? << (18 - 6 * i);
+        }
+        let bytes = bits.to_be_bytes();
+        decoded.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn request(headers: &str) -> Request {
+        let raw = format!("GET /page?x=1 HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n");
+        let mut request = Request::read_from(&mut raw.as_bytes()).unwrap().unwrap();
+        request.remote_addr = Some("10.0.0.1:5000".parse().unwrap());
+        request
+    }
+
+    fn page(_: &Request) -> Response {
+        Response::html(200, "<p>hello</p>".repeat(100))
+    }
+
+    /// 可以在测试中读取内容的输出
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn chain_order_and_access_log() {
+        struct Tag(&'static str);
+        impl Middleware for Tag {
+            fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+                let mut response = next(request);
+                response.headers.append("X-Tag", self.0);
+                response
+            }
+        }
+
+        let log = Buffer::default();
+        let handler = Chain::new()
+            .with(AccessLog::to(log.clone()))
+            .with(Tag("outer"))
+            .with(Tag("inner"))
+            .handler(page);
+        let response = handler(&request("Authorization: Basic YWxpY2U6c2VjcmV0\r\n"));
+        // 里层的中间件先处理响应
+        assert_eq!(
+            vec!["inner", "outer"],
+            response.headers.get_all("X-Tag").collect::<Vec<_>>()
+        );
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        assert!(log.starts_with("10.0.0.1 - alice ["), "{log}");
+        assert!(
+            log.ends_with("] \"GET /page?x=1 HTTP/1.1\" 200 1200\n"),
+            "{log}"
+        );
+
+        assert_eq!(
+            "18/Oct/2026:11:10:29 +0000",
+            clf_date(UNIX_EPOCH + std::time::Duration::from_secs(1792321829))
+        );
+        assert_eq!(
+            "29/Feb/2000:00:00:00 +0000",
+            clf_date(UNIX_EPOCH + std::time::Duration::from_secs(951782400))
+        );
+    }
+
+    #[test]
+    fn timing_and_gzip() {
+        let handler = Chain::new()
+            .with(Timing)
+            .with(Gzip::default())
+            .handler(page);
+        let response = handler(&request("Accept-Encoding: deflate, gzip;q=0.8\r\n"));
+        assert!(response
+            .headers
+            .get("Server-Timing")
+            .unwrap()
+            .starts_with("app;dur="));
+        assert_eq!(Some("gzip"), response.headers.get("Content-Encoding"));
+        assert_eq!(Some("Accept-Encoding"), response.headers.get("Vary"));
+        let mut body = String::new();
+        GzDecoder::new(&response.body[..])
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(page(&request("")).body, body.as_bytes());
+
+        for headers in [
+            "",
+            "Accept-Encoding: gzip;q=0\r\n",
+            "Accept-Encoding: br\r\n",
+        ] {
+            let response = handler(&request(headers));
+            assert_eq!(None, response.headers.get("Content-Encoding"), "{headers}");
+        }
+        // 太小的响应和图片不压缩
+        let gzip = Gzip::default();
+        let small = gzip.handle(&request("Accept-Encoding: *\r\n"), &|_| {
+            Response::text(200, "hi")
+        });
+        assert_eq!(None, small.headers.get("Content-Encoding"));
+        let image = gzip.handle(&request("Accept-Encoding: gzip\r\n"), &|_| {
+            Response::new(200)
+                .header("Content-Type", "image/png")
+                .body(vec![0; 1024])
+        });
+        assert_eq!(None, image.headers.get("Content-Encoding"));
+    }
+
+    #[test]
+    fn basic_auth() {
+        let handler = Chain::new()
+            .with(BasicAuth::new("admin", |user, password| {
+                user == "alice" && constant_time_eq(password, "secret")
+            }))
+            .handler(page);
+        let response = handler(&request(""));
+        assert_eq!(401, response.status);
+        assert_eq!(
+            Some("Basic realm=\"admin\", charset=\"UTF-8\""),
+            response.headers.get("WWW-Authenticate")
+        );
+        // alice:wrong
+        assert_eq!(
+            401,
+            handler(&request("Authorization: Basic YWxpY2U6d3Jvbmc=\r\n")).status
+        );
+        assert_eq!(
+            401,
+            handler(&request("Authorization: Bearer YWxpY2U6c2VjcmV0\r\n")).status
+        );
+        // alice:secret
+        assert_eq!(
+            200,
+            handler(&request("Authorization: basic YWxpY2U6c2VjcmV0\r\n")).status
+        );
+
+        assert_eq!(Some(b"ab".to_vec()), base64_decode("YWI="));
+        assert_eq!(Some(b"abc".to_vec()), base64_decode("YWJj"));
+        assert_eq!(None, base64_decode("YW!j"));
+    }
+}
@@ -0,0 +1,328 @@
+use crate::http::{Method, Request, Response};
+use crate::middleware::{Chain, Middleware};
+use std::collections::HashMap;
+use std::fs;
+
+/// 处理请求的函数，路由会在多个线程之间共享，所以必须是 `Send + Sync`
+pub type Handler = Box<dyn Fn(&Request) -> Response + Send + Sync>;
+
+/// 路径模式中的一段
+#[derive(Debug, PartialEq)]
+enum Segment {
+    /// 必须完全相同
+    Static(String),
+    /// `:name`，匹配任意一段
+    Param(String),
+    /// `*name`，只能出现在最后，匹配剩下的所有段(可以为空)
+    Rest(String),
+}
+
+struct Route {
+    method: Method,
+    segments: Vec<Segment>,
+    handler: Handler,
+}
+
+/// 路由表，按注册的顺序匹配，第一个匹配的路由处理请求
+///
+/// ```ignore
+/// let mut router = Router::new();
+/// router
+///     .get("/", |_| Response::html(200, "hello"))
+///     .get("/users/:id", |req| Response::text(200, req.param("id").unwrap().to_string()))
+///     .get("/static/*path", serve_static);
+/// let response = router.handle(&mut request);
+/// ```
+///
+/// 路径能匹配上，但是没有对应方法的路由时，回复 `405` 和列出可用方法的 `Allow` 头部；
+/// 路径都匹配不上时交给 `not_found`
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Handler,
+    middleware: Chain,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(|_| not_found_page()),
+            middleware: Chain::new(),
+        }
+    }
+
+    /// 注册一个路由
+    ///
+    /// # Panics
+    ///
+    /// 模式不以 `/` 开头，或者 `*name` 不在最后时会 panic
+    pub fn route<F>(&mut self, method: Method, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method,
+            segments: parse_pattern(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    #[allow(dead_code)]
+    pub fn post<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    /// 替换默认的 404 处理函数
+    #[allow(dead_code)]
+    pub fn not_found<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.not_found = Box::new(handler);
+        self
+    }
+
+    /// 添加对所有请求(包括 404 和 405)都生效的中间件，先添加的在最外层
+    ///
+    /// 只对某些路由生效的中间件用 [`Chain`] 包装处理函数
+    #[allow(dead_code)]
+    pub fn wrap(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// 找到匹配的路由，把路径参数填到 `request.params` 中，再经过中间件交给它处理
+    pub fn handle(&self, request: &mut Request) -> Response {
+        let endpoint = self.find(request);
+        self.middleware.run(request, &|request| match &endpoint {
+            Endpoint::Route(handler) => handler(request),
+            Endpoint::NotAllowed(allow) => {
+                Response::text(405, format!("{} is not allowed here\n", request.method))
+                    .header("Allow", allow.as_str())
+            }
+            Endpoint::NotFound => (self.not_found)(request),
+        })
+    }
+
+    fn find(&self, request: &mut Request) -> Endpoint<'_> {
+        let mut allowed = Vec::new();
+        for route in &self.routes {
+            let params = match match_path(&route.segments, &request.path) {
+                Some(params) => params,
+                None => continue,
+            };
+            // 没有单独注册 HEAD 的路由时由 GET 的路由处理，写出时去掉响应体
+            if route.method == request.method
+                || (route.method == Method::Get && request.method == Method::Head)
+            {
+                request.params = params;
+                return Endpoint::Route(&route.handler);
+            }
+            let methods: &[Method] = match route.method {
+                Method::Get => &[Method::Get, Method::Head],
+                _ => std::slice::from_ref(&route.method),
+            };
+            for method in methods {
+                if !allowed.contains(method) {
+                    allowed.push(*method);
+                }
+            }
+        }
+
+        if allowed.is_empty() {
+            return Endpoint::NotFound;
+        }
+        let allow = allowed
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        Endpoint::NotAllowed(allow)
+    }
+}
+
+/// 请求最终交给谁处理
+enum Endpoint<'a> {
+    Route(&'a Handler),
+    /// 路径匹配但是方法不对，带着 `Allow` 头部的值
+    NotAllowed(String),
+    NotFound,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 默认的 404 页面
+pub fn not_found_page() -> Response {
+    match fs::read_to_string("404.html") {
+        Ok(contents) => Response::html(404, contents),
+        Err(_) => Response::text(404, "Not Found\n"),
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    assert!(
+        pattern.starts_with('/'),
+        "route pattern must start with '/': {pattern}"
+    );
+    let segments: Vec<_> = split_path(pattern)
+        .map(|segment| {
+            if let Some(name) = segment.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = segment.strip_prefix('*') {
+                Segment::Rest(name.to_string())
+            } else {
+                Segment::Static(segment.to_string())
+            }
+        })
+        .collect();
+    let rest = segments
+        .iter()
+        .position(|segment| matches!(segment, Segment::Rest(_)));
+    assert!(
+        rest.is_none_or(|i| i == segments.len() - 1),
+        "'*' must be the last segment: {pattern}"
+    );
+    segments
+}
+
+/// 路径按 `/` 分段，`/` 本身没有段，结尾的 `/` 也会被忽略
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.trim_start_matches('/')
+        .trim_end_matches('/')
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+}
+
+fn match_path(segments: &[Segment], path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut parts = split_path(path);
+    for segment in segments {
+        match segment {
+            Segment::Static(expected) => {
+                if parts.next()? != expected {
+                    return None;
+                }
+            }
+            Segment::Param(name) => {
+                params.insert(name.clone(), parts.next()?.to_string());
+            }
+            Segment::Rest(name) => {
+                params.insert(name.clone(), parts.by_ref().collect::<Vec<_>>().join("/"));
+            }
+        }
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(params),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Timing;
+
+    fn request(method: &str, path: &str) -> Request {
+        let raw = format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        Request::read_from(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(&response.body).unwrap()
+    }
+
+    fn router() -> Router {
+        let mut router = Router::new();
+        router
+            .get("/", |_| Response::text(200, "index"))
+            .get("/users/new", |_| Response::text(200, "new user"))
+            .get("/users/:id", |req| {
+                Response::text(200, format!("user {}", req.param("id").unwrap()))
+            })
+            .route(Method::Delete, "/users/:id", |_| Response::new(204))
+            .get("/users/:id/posts/:post", |req| {
+                Response::text(
+                    200,
+                    format!(
+                        "{}/{}",
+                        req.param("id").unwrap(),
+                        req.param("post").unwrap()
+                    ),
+                )
+            })
+            .get("/files/*path", |req| {
+                Response::text(200, format!("file '{}'", req.param("path").unwrap()))
+            })
+            .not_found(|req| Response::text(404, format!("no {}", req.path)));
+        router
+    }
+
+    #[test]
+    fn match_routes_and_params() {
+        let router = router();
+        let handle = |method: &str, path: &str| router.handle(&mut request(method, path));
+        assert_eq!("index", body(&handle("GET", "/")));
+        // 先注册的路由优先
+        assert_eq!("new user", body(&handle("GET", "/users/new")));
+        assert_eq!("user 42", body(&handle("GET", "/users/42/")));
+        assert_eq!("user a b", body(&handle("GET", "/users/a%20b")));
+        assert_eq!(204, handle("DELETE", "/users/42").status);
+        assert_eq!("42/7", body(&handle("GET", "/users/42/posts/7")));
+        assert_eq!(
+            "file 'css/site.css'",
+            body(&handle("GET", "/files/css/site.css"))
+        );
+        assert_eq!("file ''", body(&handle("GET", "/files")));
+        assert_eq!(
+            "no /users/42/posts",
+            body(&handle("GET", "/users/42/posts"))
+        );
+        assert_eq!(404, handle("GET", "/nothing").status);
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        let router = router();
+        let response = router.handle(&mut request("POST", "/users/42"));
+        assert_eq!(405, response.status);
+        assert_eq!(Some("GET, HEAD, DELETE"), response.headers.get("Allow"));
+        let response = router.handle(&mut request("PUT", "/"));
+        assert_eq!(Some("GET, HEAD"), response.headers.get("Allow"));
+        let response = router.handle(&mut request("HEAD", "/users/42"));
+        assert_eq!("user 42", body(&response));
+    }
+
+    #[test]
+    fn middleware_wraps_every_request() {
+        let mut router = router();
+        router.wrap(Timing);
+        for (method, path) in [("GET", "/"), ("POST", "/"), ("GET", "/nothing")] {
+            let response = router.handle(&mut request(method, path));
+            assert!(
+                response.headers.contains("Server-Timing"),
+                "{method} {path}"
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "'*' must be the last segment")]
+    fn rest_must_be_last() {
+        Router::new().get("/files/*path/edit", |_| Response::new(200));
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{self, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// 记录所有打开的连接，用于优雅关闭:
+///
+/// 1. `stop` 之后不再接受新连接，空闲的持久连接直接关闭，正在处理的请求回复之后关闭
+/// 2. `drain` 等待正在处理的请求完成，超过期限还没完成的连接会被强制关闭
+#[derive(Default)]
+pub struct Connections {
+    stopping: AtomicBool,
+    open: Mutex<HashMap<u64, Tracked>>,
+    /// 每关闭一个连接通知一次 `drain`
+    closed: Condvar,
+    next_id: AtomicU64,
+    accepted: AtomicUsize,
+    requests: AtomicUsize,
+    #[allow(dead_code)]
+    aborted: AtomicUsize,
+}
+
+struct Tracked {
+    /// 用来在关闭时中断阻塞的读写
+    #[allow(dead_code)]
+    stream: TcpStream,
+    /// 是否正在处理请求
+    busy: bool,
+}
+
+/// 关闭时的统计信息
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+pub struct Summary {
+    pub connections: usize,
+    pub requests: usize,
+    /// 超过期限还没处理完、被强制关闭的连接
+    pub aborted: usize,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} requests served on {} connections, {} connections aborted",
+            self.requests, self.connections, self.aborted
+        )
+    }
+}
+
+impl Connections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    /// 记录一个新连接，连接关闭(`Guard` 被 drop)时自动删除
+    ///
+    /// 已经开始关闭时返回 `None`，调用方应该直接关闭这个连接
+    pub fn register(&self, stream: &TcpStream) -> Option<Guard<'_>> {
+        let stream = stream.try_clone().ok()?;
+        let mut open = self.open.lock().unwrap();
+        if self.is_stopping() {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        open.insert(
+            id,
+            Tracked {
+                stream,
+                busy: false,
+            },
+        );
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+        Some(Guard {
+            connections: self,
+            id,
+        })
+    }
+
+    /// 开始关闭: 不再接受新连接，关闭所有空闲的连接
+    #[allow(dead_code)]
+    pub fn stop(&self) {
+        let open = self.open.lock().unwrap();
+        self.stopping.store(true, Ordering::SeqCst);
+        for tracked in open.values().filter(|tracked| !tracked.busy) {
+            let _ = tracked.stream.shutdown(net::Shutdown::Both);
+        }
+    }
+
+    /// 等待所有连接关闭，最多等待 `timeout`，之后强制关闭剩下的连接
+    #[allow(dead_code)]
+    pub fn drain(&self, timeout: Duration) -> Summary {
+        let deadline = Instant::now() + timeout;
+        let mut open = self.open.lock().unwrap();
+        while !open.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            open = self.closed.wait_timeout(open, deadline - now).unwrap().0;
+        }
+        for tracked in open.values() {
+            let _ = tracked.stream.shutdown(net::Shutdown::Both);
+        }
+        self.aborted.fetch_add(open.len(), Ordering::Relaxed);
+        open.clear();
+        self.summary()
+    }
+
+    #[allow(dead_code)]
+    pub fn summary(&self) -> Summary {
+        Summary {
+            connections: self.accepted.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            aborted: self.aborted.load(Ordering::Relaxed),
+        }
+    }
+
+    fn set_busy(&self, id: u64, busy: bool) -> bool {
+        let mut open = self.open.lock().unwrap();
+        if let Some(tracked) = open.get_mut(&id) {
+            tracked.busy = busy;
+        }
+        !self.is_stopping()
+    }
+}
+
+/// 一个被记录的连接
+pub struct Guard<'a> {
+    connections: &'a Connections,
+    id: u64,
+}
+
+impl Guard<'_> {
+    /// 读到一个完整的请求，开始处理
+    pub fn busy(&self) {
+        self.connections.set_busy(self.id, true);
+    }
+
+    /// 回复完一个请求，开始等待下一个请求
+    ///
+    /// 已经开始关闭时返回 `false`，调用方应该关闭连接
+    pub fn idle(&self) -> bool {
+        self.connections.requests.fetch_add(1, Ordering::Relaxed);
+        self.connections.set_busy(self.id, false)
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.connections.is_stopping()
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        let mut open = self.connections.open.lock().unwrap();
+        open.remove(&self.id);
+        self.connections.closed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn pair(listener: &TcpListener) -> (TcpStream, TcpStream) {
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (listener.accept().unwrap().0, client)
+    }
+
+    #[test]
+    fn stop_and_drain() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let connections = Connections::new();
+        let (idle, mut idle_client) = pair(&listener);
+        let (busy, _busy_client) = pair(&listener);
+        let (stuck, _stuck_client) = pair(&listener);
+
+        thread::scope(|scope| {
+            let idle_guard = connections.register(&idle).unwrap();
+            let busy_guard = connections.register(&busy).unwrap();
+            let stuck_guard = connections.register(&stuck).unwrap();
+            busy_guard.busy();
+            stuck_guard.busy();
+
+            connections.stop();
+            assert!(connections.register(&idle).is_none());
+            // 空闲的连接被关闭了
+            assert_eq!(0, idle_client.read(&mut [0; 1]).unwrap());
+            drop(idle_guard);
+
+            // 正在处理的请求回复之后要关闭连接
+            scope.spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                assert!(!busy_guard.idle());
+            });
+            let start = Instant::now();
+            let summary = connections.drain(Duration::from_millis(300));
+            assert!(start.elapsed() >= Duration::from_millis(300));
+            assert_eq!(
+                Summary {
+                    connections: 3,
+                    requests: 1,
+                    aborted: 1,
+                },
+                summary
+            );
+            drop(stuck_guard);
+        });
+    }
+}
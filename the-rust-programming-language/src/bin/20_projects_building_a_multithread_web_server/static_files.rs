@@ -0,0 +1,358 @@
+use crate::http::{Method, Request, Response};
+use crate::router::not_found_page;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 目录请求时返回的文件
+const INDEX_FILE: &str = "index.html";
+
+/// 扩展名和 MIME 类型的对应关系，找不到时用 `application/octet-stream`
+const MIME_TYPES: [(&str, &str); 26] = [
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("ico", "image/x-icon"),
+    ("pdf", "application/pdf"),
+    ("wasm", "application/wasm"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+];
+
+/// 把某个目录下的文件作为静态文件提供，支持:
+///
+/// - 根据扩展名设置 `Content-Type`
+/// - `ETag`、`Last-Modified`，以及 `If-None-Match`、`If-Modified-Since` 的 `304`
+/// - 单个 `Range: bytes=...` 的 `206`，以及 `If-Range`
+/// - 拒绝 `..` 或者符号链接跳出根目录的请求
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        StaticFiles { root: root.into() }
+    }
+
+    /// 给路由使用的处理函数，`param` 是 `*name` 路径参数的名字
+    ///
+    /// ```ignore
+    /// router.get("/static/*path", StaticFiles::new("public").handler("path"));
+    /// ```
+    pub fn handler(self, param: &'static str) -> impl Fn(&Request) -> Response + Send + Sync {
+        move |request| self.serve(request, request.param(param).unwrap_or_default())
+    }
+
+    /// 返回根目录下的 `path`，`path` 是相对于根目录、以 `/` 分隔、已经解码的路径
+    pub fn serve(&self, request: &Request, path: &str) -> Response {
+        let mut file_path = match self.resolve(path) {
+            Some(file_path) => file_path,
+            None => return Response::text(403, "Forbidden\n"),
+        };
+        let mut metadata = match fs::metadata(&file_path) {
+            Ok(metadata) => metadata,
+            Err(_) => return not_found_page(),
+        };
+        if metadata.is_dir() {
+            file_path.push(INDEX_FILE);
+            metadata = match fs::metadata(&file_path) {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => return not_found_page(),
+            };
+        }
+        // 符号链接可能指向根目录之外
+        if !self.is_inside_root(&file_path) {
+            return Response::text(403, "Forbidden\n");
+        }
+
+        let len = metadata.len();
+        let modified = metadata.modified().ok().map(truncate_to_secs);
+        let etag = etag(len, modified);
+        let mut response = Response::new(200)
+            .header("Content-Type", mime_type(&file_path))
+            .header("Accept-Ranges", "bytes")
+            .header("ETag", etag.as_str());
+        if let Some(modified) = modified {
+            response = response.header("Last-Modified", httpdate::fmt_http_date(modified));
+        }
+
+        if is_not_modified(request, &etag, modified) {
+            response.status = 304;
+            response.headers.remove("Content-Type");
+            return response;
+        }
+
+        let range = match request.headers.get("Range") {
+            Some(range) if request.method == Method::Get && if_range(request, &etag, modified) => {
+                parse_range(range, len)
+            }
+            _ => None,
+        };
+        let result = match range {
+            Some(Ok((start, end))) => read_range(&file_path, start, end).map(|body| {
+                response.status = 206;
+                response
+                    .header("Content-Range", format!("bytes {start}-{end}/{len}"))
+                    .body(body)
+            }),
+            Some(Err(())) => {
+                return Response::text(416, "Range Not Satisfiable\n")
+                    .header("Content-Range", format!("bytes */{len}"))
+            }
+            None => fs::read(&file_path).map(|body| response.body(body)),
+        };
+        result.unwrap_or_else(|e| Response::text(500, format!("Can't read file: {e}\n")))
+    }
+
+    /// 把请求的路径拼到根目录上，有 `..`、绝对路径或者 Windows 盘符时返回 `None`
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut resolved = self.root.clone();
+        for segment in path.split('/') {
+            // 反斜杠在 Windows 上是路径分隔符
+            if segment.contains('\\') || segment.contains('\0') {
+                return None;
+            }
+            for component in Path::new(segment).components() {
+                match component {
+                    Component::Normal(name) => resolved.push(name),
+                    Component::CurDir => {}
+                    Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                        return None
+                    }
+                }
+            }
+        }
+        Some(resolved)
+    }
+
+    fn is_inside_root(&self, path: &Path) -> bool {
+        match (self.root.canonicalize(), path.canonicalize()) {
+            (Ok(root), Ok(path)) => path.starts_with(root),
+            _ => false,
+        }
+    }
+}
+
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    MIME_TYPES
+        .iter()
+        .find(|(ext, _)| *ext == extension)
+        .map_or("application/octet-stream", |(_, mime)| mime)
+}
+
+/// HTTP 的日期只精确到秒，比较之前要先去掉小数部分
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// 由文件长度和修改时间生成，文件变了 ETag 基本上就会变
+fn etag(len: u64, modified: Option<SystemTime>) -> String {
+    let secs = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs());
+    format!("\"{secs:x}-{len:x}\"")
+}
+
+/// `If-None-Match` 优先于 `If-Modified-Since`，比较 ETag 时忽略弱标记 `W/`
+fn is_not_modified(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if !matches!(request.method, Method::Get | Method::Head) {
+        return false;
+    }
+    if let Some(if_none_match) = request.headers.get("If-None-Match") {
+        return if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag);
+    }
+    match (request.headers.get("If-Modified-Since"), modified) {
+        (Some(since), Some(modified)) => {
+            httpdate::parse_http_date(since).is_ok_and(|since| modified <= since)
+        }
+        _ => false,
+    }
+}
+
+/// 没有 `If-Range`，或者它和当前文件的 ETag/修改时间一致时才处理 `Range`
+fn if_range(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    match request.headers.get("If-Range") {
+        None => true,
+        Some(tag) if tag.starts_with('"') => tag == etag,
+        Some(date) => httpdate::parse_http_date(date).ok() == modified,
+    }
+}
+
+/// 解析 `bytes=start-end`、`bytes=start-` 和 `bytes=-suffix`，返回闭区间
+///
+/// 格式不对或者有多个范围时返回 `None`，按照 RFC 9110 忽略 `Range` 返回整个文件；
+/// 范围完全在文件之外时返回 `Some(Err(()))`，需要回复 `416`
+fn parse_range(range: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.trim().split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 || len == 0 {
+                return Some(Err(()));
+            }
+            (len.saturating_sub(suffix), len - 1)
+        }
+        (start, "") => (start.parse().ok()?, len.saturating_sub(1)),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            if end < start {
+                return None;
+            }
+            (start, end.min(len.saturating_sub(1)))
+        }
+    };
+    if start >= len {
+        return Some(Err(()));
+    }
+    Some(Ok((start, end)))
+}
+
+fn read_range(path: &Path, start: u64, end: u64) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut body = Vec::with_capacity((end - start + 1) as usize);
+    file.take(end - start + 1).read_to_end(&mut body)?;
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn request(headers: &str) -> Request {
+        let raw = format!("GET /static HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n");
+        Request::read_from(&mut raw.as_bytes()).unwrap().unwrap()
+    }
+
+    /// 每个测试用不同的目录，避免并行运行时互相影响
+    fn root(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("web-server-{name}-{}", std::process::id()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("index.html"), "<h1>home</h1>").unwrap();
+        fs::write(root.join("docs/notes.txt"), "0123456789").unwrap();
+        fs::write(root.join("logo.PNG"), [0x89, b'P', b'N', b'G', 0xFF]).unwrap();
+        root
+    }
+
+    #[test]
+    fn serve_files_with_types_and_validators() {
+        let files = StaticFiles::new(root("types"));
+        let response = files.serve(&request(""), "docs/notes.txt");
+        assert_eq!(200, response.status);
+        assert_eq!(b"0123456789", &response.body[..]);
+        assert_eq!(
+            Some("text/plain; charset=utf-8"),
+            response.headers.get("Content-Type")
+        );
+        let response = files.serve(&request(""), "logo.PNG");
+        assert_eq!(Some("image/png"), response.headers.get("Content-Type"));
+        assert_eq!(&[0x89, b'P', b'N', b'G', 0xFF], &response.body[..]);
+        // 目录返回其中的 index.html
+        let response = files.serve(&request(""), "");
+        assert_eq!(b"<h1>home</h1>", &response.body[..]);
+        assert_eq!(404, files.serve(&request(""), "missing.txt").status);
+        assert_eq!(404, files.serve(&request(""), "docs").status);
+
+        let response = files.serve(&request(""), "docs/notes.txt");
+        let etag = response.headers.get("ETag").unwrap();
+        let modified = response.headers.get("Last-Modified").unwrap();
+        let response = files.serve(
+            &request(&format!("If-None-Match: \"x\", W/{etag}\r\n")),
+            "docs/notes.txt",
+        );
+        assert_eq!(304, response.status);
+        assert!(response.body.is_empty());
+        let response = files.serve(
+            &request(&format!("If-Modified-Since: {modified}\r\n")),
+            "docs/notes.txt",
+        );
+        assert_eq!(304, response.status);
+        let response = files.serve(
+            &request("If-Modified-Since: Thu, 01 Jan 1970 00:00:00 GMT\r\n"),
+            "docs/notes.txt",
+        );
+        assert_eq!(200, response.status);
+    }
+
+    #[test]
+    fn range_requests() {
+        let files = StaticFiles::new(root("ranges"));
+        let serve = |headers: &str| files.serve(&request(headers), "docs/notes.txt");
+        let response = serve("Range: bytes=2-4\r\n");
+        assert_eq!(206, response.status);
+        assert_eq!(b"234", &response.body[..]);
+        assert_eq!(Some("bytes 2-4/10"), response.headers.get("Content-Range"));
+        assert_eq!(b"789", &serve("Range: bytes=7-\r\n").body[..]);
+        assert_eq!(b"6789", &serve("Range: bytes=-4\r\n").body[..]);
+        assert_eq!(b"89", &serve("Range: bytes=8-100\r\n").body[..]);
+
+        let response = serve("Range: bytes=10-\r\n");
+        assert_eq!(416, response.status);
+        assert_eq!(Some("bytes */10"), response.headers.get("Content-Range"));
+        // 多个范围和格式错误的范围都返回整个文件
+        assert_eq!(200, serve("Range: bytes=0-1,4-5\r\n").status);
+        assert_eq!(200, serve("Range: lines=1-2\r\n").status);
+        // If-Range 不匹配时返回整个文件
+        let response = serve("Range: bytes=0-1\r\nIf-Range: \"stale\"\r\n");
+        assert_eq!(200, response.status);
+        assert_eq!(10, response.body.len());
+    }
+
+    #[test]
+    fn reject_path_traversal() {
+        let root = root("traversal");
+        let files = StaticFiles::new(root.join("docs"));
+        for path in [
+            "../index.html",
+            "a/../../index.html",
+            "..\\index.html",
+            "C:\\Windows",
+        ] {
+            assert_eq!(403, files.serve(&request(""), path).status, "{path}");
+        }
+        #[cfg(unix)]
+        {
+            let link = root.join("docs/escape.html");
+            let _ = fs::remove_file(&link);
+            std::os::unix::fs::symlink(root.join("index.html"), &link).unwrap();
+            assert_eq!(403, files.serve(&request(""), "escape.html").status);
+        }
+    }
+}
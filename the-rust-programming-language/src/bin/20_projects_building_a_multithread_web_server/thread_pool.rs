@@ -1,18 +1,319 @@
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{mpsc, Arc, Mutex};
+use crossbeam_deque::{Injector, Steal, Stealer, Worker};
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock};
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::Duration;
 
+/// `Scheduler::WorkStealing` 的 worker 找不到任务时，睡眠之前再试几次
+const SPINS: usize = 16;
 type Job = Box<dyn FnOnce() + Send + 'static>;
-enum Message {
-    NewJob(Job),
-    Terminate,
+/// 任务 panic 时调用，参数是 worker 的 id 和 panic 的信息
+type PanicHook = Box<dyn Fn(usize, &str) + Send + Sync>;
+/// `Rejection::CallerRuns` 在调用方线程中执行的任务 panic 时，panic hook 收到的 worker id
+pub const CALLER: usize = usize::MAX;
+/// 提交任务失败
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum PoolError {
+    #[error("the thread pool has been shut down")]
+    ShutDown,
+    /// 有界队列满了，并且设置了 `Rejection::Reject`
+    #[error("the job queue is full")]
+    Full,
 }
+
+/// 等待任务结果失败
+#[derive(thiserror::Error, Debug, PartialEq)]
+#[allow(dead_code)]
+pub enum JoinError {
+    /// 任务没有返回结果就结束了，或者结果已经被取走了
+    #[error("the job finished without a result")]
+    Lost,
+    #[error("the job panicked: {0}")]
+    Panicked(String),
+}
+
+/// `submit` 返回的句柄，用来取得任务的返回值，结果只能取一次
+///
+/// 丢掉句柄不会取消任务，只是不再关心它的结果
+#[allow(dead_code)]
+pub struct JobHandle<T> {
+    receiver: Receiver<Result<T, String>>,
+}
+
+impl<T> JobHandle<T> {
+    /// 阻塞直到任务完成
+    #[allow(dead_code)]
+    pub fn join(self) -> Result<T, JoinError> {
+        match self.receiver.recv() {
+            Ok(result) => result.map_err(JoinError::Panicked),
+            Err(_) => Err(JoinError::Lost),
+        }
+    }
+
+    /// 不阻塞，任务还没完成时返回 `Ok(None)`
+    #[allow(dead_code)]
+    pub fn try_join(&self) -> Result<Option<T>, JoinError> {
+        match self.receiver.try_recv() {
+            Ok(result) => result.map(Some).map_err(JoinError::Panicked),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(JoinError::Lost),
+        }
+    }
+
+    /// 最多等待 `timeout`，超时时返回 `Ok(None)`，之后还可以继续等待
+    #[allow(dead_code)]
+    pub fn join_timeout(&self, timeout: Duration) -> Result<Option<T>, JoinError> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(result) => result.map(Some).map_err(JoinError::Panicked),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(JoinError::Lost),
+        }
+    }
+}
+
 /// 自定义线程池
+///
+/// 线程数在 `min_threads` 和 `max_threads` 之间变化: 队列中等待的任务比空闲的 worker 多时
+/// 启动新的 worker，多出 `min_threads` 的 worker 空闲超过 `keep_alive` 后退出
+///
+/// worker 取任务的方式由 [`Scheduler`] 决定，默认所有 worker 共用一个加锁的队列
+///
+/// 任务 panic 不会影响 worker，panic 的信息交给 `set_panic_hook` 设置的函数(`submit`
+/// 的任务通过 `JobHandle` 返回)；worker 线程因为其他原因意外退出时会自动启动一个新的线程
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    /// 任务队列
-    sender: Option<Sender<Message>>,
+    shared: Arc<Shared>,
+}
+/// 用来创建 [`ThreadPool`]
+///
+/// ```ignore
+/// let pool = ThreadPool::builder()
+///     .min_threads(2)
+///     .max_threads(16)
+///     .keep_alive(Duration::from_secs(30))
+///     .thread_name("web")
+///     .scheduler(Scheduler::WorkStealing)
+///     .queue_capacity(1024)
+///     .rejection(Rejection::Reject)
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Builder {
+    min_threads: usize,
+    max_threads: usize,
+    keep_alive: Duration,
+    thread_name: String,
+    scheduler: Scheduler,
+    /// `None` 表示队列没有上限
+    queue_capacity: Option<usize>,
+    rejection: Rejection,
+    verbose: bool,
+}
+/// worker 怎样从队列中取任务
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Scheduler {
+    /// 所有 worker 共用一个 `Mutex<VecDeque>`，每次取任务都要抢同一把锁
+    ///
+    /// 任务多、每个任务又很小的时候，大部分时间都花在等锁和唤醒线程上了
+    #[default]
+    Shared,
+    /// 每个 worker 有自己的队列，外部提交的任务放在全局的 injector 中:
+    ///
+    /// 1. worker 先从自己的队列中取任务
+    /// 2. 自己的队列空了，就从 injector 中一次拿一批到自己的队列
+    /// 3. injector 也空了，就从别的 worker 的队列中偷任务
+    ///
+    /// 取任务不需要加锁，只有 worker 没事可做、准备睡眠时才要拿锁
+    #[allow(dead_code)]
+    WorkStealing,
+}
+/// 有界队列满了之后怎样处理新任务
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Rejection {
+    /// 阻塞调用方，直到队列中有空位
+    #[default]
+    Block,
+    /// `execute` 返回 `PoolError::Full`，任务被丢掉
+    #[allow(dead_code)]
+    Reject,
+    /// 丢掉队列中最早的任务，给新任务腾出位置，没有可以丢掉的任务时和 `Reject` 一样
+    #[allow(dead_code)]
+    DropOldest,
+    /// 在调用 `execute` 的线程中直接执行新任务，任务 panic 时和在 worker 中一样交给 panic hook
+    ///
+    /// 调用方忙着执行任务的时候没法提交新任务，提交的速度自然就慢下来了
+    #[allow(dead_code)]
+    CallerRuns,
+}
+/// 线程池在某一时刻的状态
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[allow(dead_code)]
+pub struct Metrics {
+    /// 活着的 worker 数
+    pub threads: usize,
+    /// 正在执行任务的 worker 数(包括 `Rejection::CallerRuns` 时执行任务的调用方)
+    pub active: usize,
+    /// 正在等待任务的 worker 数
+    pub idle: usize,
+    /// 队列中等待执行的任务数
+    pub queued: usize,
+    /// 已经执行完的任务数(包括 panic 的任务)
+    pub completed: usize,
+    pub panicked: usize,
+    /// 因为队列满了被拒绝或者被挤出队列的任务数
+    pub rejected: usize,
+}
+/// 不拥有线程池，只用来读取 [`Metrics`]，可以随意复制、在线程之间传递
+#[derive(Clone)]
+#[allow(dead_code)]
+pub struct Monitor {
+    shared: Arc<Shared>,
+}
+/// 所有 worker 共享的状态
+struct Shared {
+    state: Mutex<State>,
+    /// 有新任务或者开始关闭时通知空闲的 worker
+    available: Condvar,
+    /// 有界队列有空位或者开始关闭时通知 `Rejection::Block` 阻塞的调用方
+    space: Condvar,
+    /// `Scheduler::WorkStealing` 的全局队列
+    injector: Injector<Job>,
+    /// 每个 worker 自己的队列的 `Stealer`，用来从别的 worker 那里偷任务
+    stealers: RwLock<HashMap<usize, Stealer<Job>>>,
+    panic_hook: RwLock<Option<PanicHook>>,
+    config: Builder,
+    /// 下面四个只在拿着 `state` 的锁时修改，不拿锁也可以读
+    stopping: AtomicBool,
+    threads: AtomicUsize,
+    idle: AtomicUsize,
+    /// 因为队列满了正在阻塞的调用方
+    blocked: AtomicUsize,
+    /// 两种 `Scheduler` 的队列中一共有多少任务
+    queued: AtomicUsize,
+    /// 有界队列中被占用的位置: 队列中的任务加上已经占到位置、还没放进队列的任务
+    reserved: AtomicUsize,
+    active: AtomicUsize,
+    completed: AtomicUsize,
+    panicked: AtomicUsize,
+    rejected: AtomicUsize,
+}
+/// 要在同一个锁下面修改的状态
+#[derive(Default)]
+struct State {
+    /// `Scheduler::Shared` 的任务队列
+    jobs: VecDeque<Job>,
+    next_id: usize,
+    /// 空闲退出的 worker 会删掉自己的 `JoinHandle`，剩下的在 `shutdown` 时等待它们退出
+    handles: HashMap<usize, JoinHandle<()>>,
+}
+impl Default for Builder {
+    fn default() -> Self {
+        Builder {
+            min_threads: 1,
+            max_threads: thread::available_parallelism().map_or(4, |n| n.get()),
+            keep_alive: Duration::from_secs(60),
+            thread_name: String::from("worker"),
+            scheduler: Scheduler::default(),
+            queue_capacity: None,
+            rejection: Rejection::default(),
+            verbose: false,
+        }
+    }
+}
+impl Builder {
+    /// 一直保留的 worker 数，创建线程池时就会启动，默认是 1
+    pub fn min_threads(mut self, min_threads: usize) -> Self {
+        self.min_threads = min_threads;
+        self
+    }
+    /// 最多同时运行的 worker 数，默认是 CPU 的核数
+    pub fn max_threads(mut self, max_threads: usize) -> Self {
+        self.max_threads = max_threads;
+        self
+    }
+    /// 多出 `min_threads` 的 worker 空闲多久后退出，默认是 60 秒
+    #[allow(dead_code)]
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+    /// worker 线程的名字是 `{prefix}-{id}`，默认是 `worker-{id}`
+    #[allow(dead_code)]
+    pub fn thread_name(mut self, prefix: impl Into<String>) -> Self {
+        self.thread_name = prefix.into();
+        self
+    }
+    /// 默认是 `Scheduler::Shared`
+    #[allow(dead_code)]
+    pub fn scheduler(mut self, scheduler: Scheduler) -> Self {
+        self.scheduler = scheduler;
+        self
+    }
+    /// 队列中最多放多少个等待执行的任务，满了之后按 `rejection` 处理，默认没有上限
+    #[allow(dead_code)]
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+    /// 默认是 `Rejection::Block`，没有设置 `queue_capacity` 时不起作用
+    #[allow(dead_code)]
+    pub fn rejection(mut self, rejection: Rejection) -> Self {
+        self.rejection = rejection;
+        self
+    }
+    /// 是否打印 worker 的运行日志，默认不打印
+    #[allow(dead_code)]
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+    /// # Panics
+    ///
+    /// `max_threads` 或 `queue_capacity` 是 0、`min_threads` 大于 `max_threads`
+    /// 或者启动线程失败时会 panic
+    pub fn build(self) -> ThreadPool {
+        assert!(self.max_threads > 0, "max_threads must be greater than 0");
+        assert_ne!(
+            Some(0),
+            self.queue_capacity,
+            "queue_capacity must be greater than 0"
+        );
+        assert!(
+            self.min_threads <= self.max_threads,
+            "min_threads must not be greater than max_threads"
+        );
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            available: Condvar::new(),
+            space: Condvar::new(),
+            injector: Injector::new(),
+            stealers: RwLock::new(HashMap::new()),
+            panic_hook: RwLock::new(None),
+            config: self,
+            stopping: AtomicBool::new(false),
+            threads: AtomicUsize::new(0),
+            idle: AtomicUsize::new(0),
+            blocked: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+            reserved: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            completed: AtomicUsize::new(0),
+            panicked: AtomicUsize::new(0),
+            rejected: AtomicUsize::new(0),
+        });
+        {
+            let mut state = lock(&shared.state);
+            for _ in 0..shared.config.min_threads {
+                spawn(&shared, &mut state).expect("failed to spawn a worker thread");
+            }
+        }
+        ThreadPool { shared }
+    }
 }
 impl ThreadPool {
     /// Create a new ThreadPool.
@@ -22,99 +323,927 @@ impl ThreadPool {
     /// # Panics
     ///
     /// The `new` function will panic if the size is zero.
+    #[allow(dead_code)]
     pub fn new(size: usize) -> ThreadPool {
+        Self::fixed(size).verbose(true).build()
+    }
+    /// 和 `new` 一样，但是不打印 worker 的运行日志
+    ///
+    /// 给 minigrep 这种会把结果输出到标准输出的程序使用
+    #[allow(dead_code)]
+    pub fn quiet(size: usize) -> ThreadPool {
+        Self::fixed(size).build()
+    }
+    #[allow(dead_code)]
+    fn fixed(size: usize) -> Builder {
         assert!(size > 0);
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
-        }
-        Self {
-            workers,
-            sender: Some(sender),
-        }
+        Self::builder().min_threads(size).max_threads(size)
+    }
+    pub fn builder() -> Builder {
+        Builder::default()
     }
-    pub fn execute<F>(&self, f: F)
+    /// 设置任务 panic 时调用的函数，参数是 worker 的 id 和 panic 的信息
+    ///
+    /// 没有设置时只在 verbose 模式下打印一行日志(panic 的信息标准库已经打印过了)
+    #[allow(dead_code)]
+    pub fn set_panic_hook<F>(&self, hook: F)
+    where
+        F: Fn(usize, &str) + Send + Sync + 'static,
+    {
+        *self
+            .shared
+            .panic_hook
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Box::new(hook));
+    }
+    /// 把任务放到队列中，线程池已经关闭时返回 `PoolError::ShutDown`
+    ///
+    /// 等待的任务比空闲的 worker 多、并且还没到 `max_threads` 时启动一个新的 worker；
+    /// 有界队列满了时按 `Rejection` 处理
+    pub fn execute<F>(&self, f: F) -> Result<(), PoolError>
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
-        if self
-            .sender
-            .as_ref()
-            .unwrap()
-            .send(Message::NewJob(job))
-            .is_err()
+        let job = match self.shared.admit(Box::new(f))? {
+            Some(job) => job,
+            // 已经在当前线程中执行完了
+            None => return Ok(()),
+        };
+        let result = match self.shared.config.scheduler {
+            Scheduler::Shared => self.shared.push(job),
+            Scheduler::WorkStealing => self.shared.inject(job),
+        };
+        // 任务没有放进队列，让出 `admit` 占到的位置
+        if result.is_err() {
+            self.shared.release();
+        }
+        result
+    }
+    /// 和 `execute` 一样，但是可以通过返回的 `JobHandle` 取得任务的返回值
+    ///
+    /// 任务 panic 时 `join` 返回 `JoinError::Panicked`，不会调用 panic hook
+    #[allow(dead_code)]
+    pub fn submit<F, T>(&self, f: F) -> Result<JobHandle<T>, PoolError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        // 每个任务一个只用一次的 channel
+        let (sender, receiver) = mpsc::channel();
+        self.execute(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f)).map_err(panic_message);
+            // 句柄已经被丢掉时没有人关心结果
+            let _ = sender.send(result);
+        })?;
+        Ok(JobHandle { receiver })
+    }
+    #[allow(dead_code)]
+    pub fn metrics(&self) -> Metrics {
+        self.shared.metrics()
+    }
+    /// 返回一个可以在别的线程中读取 [`Metrics`] 的句柄
+    #[allow(dead_code)]
+    pub fn monitor(&self) -> Monitor {
+        Monitor {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+impl ThreadPool {
+    /// 通知所有 worker 处理完已经在队列中的任务后退出，并等待它们退出
+    ///
+    /// 之后再调用 `execute` 的任务不会被执行，`drop` 时也会自动调用
+    pub fn shutdown(&mut self) {
+        let verbose = self.shared.config.verbose;
         {
-            println!("send job failed...");
+            let _state = lock(&self.shared.state);
+            if !self.shared.stopping.swap(true, Ordering::SeqCst) && verbose {
+                println!("Sending terminate message to all workers.");
+            }
+        }
+        self.shared.available.notify_all();
+        self.shared.space.notify_all();
+        // 等待的时候 worker 可能又启动了新的 worker，所以要循环到没有 worker 为止
+        loop {
+            let handles: Vec<_> = lock(&self.shared.state).handles.drain().collect();
+            if handles.is_empty() {
+                break;
+            }
+            for (id, thread) in handles {
+                if verbose {
+                    println!("Shutting down worker {}", id);
+                }
+                // 在 drop 中 panic 会直接终止程序，所以不能 unwrap
+                if thread.join().is_err() && verbose {
+                    println!("Worker {} exited abnormally", id);
+                }
+            }
+        }
+        // `inject` 和关闭同时发生时，任务可能在所有 worker 都退出之后才放进 injector，
+        // 这里替它们执行掉
+        while let Some(job) = steal(&self.shared.injector) {
+            self.shared.taken();
+            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            self.shared.completed.fetch_add(1, Ordering::SeqCst);
         }
     }
 }
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        // 关闭sender后，将关闭对应的channel
-        if let Some(sender) = self.sender.take() {
-            println!("Sending terminate message to all workers.");
-            for _ in &mut self.workers {
-                sender.send(Message::Terminate).unwrap();
+        self.shutdown();
+    }
+}
+impl Monitor {
+    #[allow(dead_code)]
+    pub fn metrics(&self) -> Metrics {
+        self.shared.metrics()
+    }
+}
+impl Shared {
+    /// 在有界队列中为新任务占一个位置，队列满了时按 `Rejection` 处理
+    ///
+    /// 返回 `None` 表示任务已经在当前线程中执行完了(`Rejection::CallerRuns`)
+    fn admit(&self, job: Job) -> Result<Option<Job>, PoolError> {
+        // 先检查是否已经关闭，否则关闭之后 `Reject` 可能返回 `Full`，`CallerRuns` 还会执行任务
+        if self.stopping.load(Ordering::SeqCst) {
+            return Err(PoolError::ShutDown);
+        }
+        let Some(capacity) = self.config.queue_capacity else {
+            return Ok(Some(job));
+        };
+        if self.reserve(capacity) {
+            return Ok(Some(job));
+        }
+        match self.config.rejection {
+            Rejection::Block => {
+                let mut state = lock(&self.state);
+                self.blocked.fetch_add(1, Ordering::SeqCst);
+                // 和 `release` 配对: 要么这里占到了位置，要么 `release` 看到有调用方在阻塞
+                let admitted = loop {
+                    if self.stopping.load(Ordering::SeqCst) {
+                        break false;
+                    }
+                    if self.reserve(capacity) {
+                        break true;
+                    }
+                    state = self
+                        .space
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                };
+                self.blocked.fetch_sub(1, Ordering::SeqCst);
+                match admitted {
+                    true => Ok(Some(job)),
+                    false => Err(PoolError::ShutDown),
+                }
+            }
+            Rejection::Reject => {
+                self.rejected.fetch_add(1, Ordering::SeqCst);
+                Err(PoolError::Full)
+            }
+            Rejection::DropOldest => {
+                // 被丢掉的任务在 drop 时可能会做一些事情(比如回复 503)，不能拿着锁 drop
+                match self.evict() {
+                    // 新任务直接用被挤掉的任务的位置
+                    Some(oldest) => {
+                        self.queued.fetch_sub(1, Ordering::SeqCst);
+                        self.rejected.fetch_add(1, Ordering::SeqCst);
+                        drop(oldest);
+                        Ok(Some(job))
+                    }
+                    // 没有能挤掉的任务: 位置都被还没放进队列的任务占着，或者任务刚被 worker
+                    // 取走、还没让出位置，这时再试一次，还是占不到就拒绝新任务
+                    None if self.reserve(capacity) => Ok(Some(job)),
+                    None => {
+                        self.rejected.fetch_add(1, Ordering::SeqCst);
+                        Err(PoolError::Full)
+                    }
+                }
             }
+            Rejection::CallerRuns => {
+                self.run_job(CALLER, job);
+                Ok(None)
+            }
+        }
+    }
+    /// 从队列中取出最早的任务
+    fn evict(&self) -> Option<Job> {
+        match self.config.scheduler {
+            Scheduler::Shared => lock(&self.state).jobs.pop_front(),
+            // worker 的队列中的任务是之前从 injector 中一批拿走的，比 injector 中剩下的更早，
+            // `Stealer` 从队列的另一端偷，偷到的是这个 worker 的队列中最早的任务
+            Scheduler::WorkStealing => self
+                .stealers
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .values()
+                .find_map(|stealer| {
+                    std::iter::repeat_with(|| stealer.steal())
+                        .find(|steal| !steal.is_retry())
+                        .and_then(Steal::success)
+                })
+                .or_else(|| steal(&self.injector)),
+        }
+    }
+    /// 占一个位置，检查和占用是同一个原子操作，多个线程同时提交也不会超过上限
+    fn reserve(&self, capacity: usize) -> bool {
+        self.reserved
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |reserved| {
+                (reserved < capacity).then_some(reserved + 1)
+            })
+            .is_ok()
+    }
+    /// 有界队列中空出了一个位置，通知因为队列满了阻塞的调用方
+    fn release(&self) {
+        if self.config.queue_capacity.is_none() {
+            return;
+        }
+        self.reserved.fetch_sub(1, Ordering::SeqCst);
+        if self.blocked.load(Ordering::SeqCst) > 0 {
+            // 拿一下锁再通知，和 `inject` 一样防止错过通知
+            let _state = lock(&self.state);
+            self.space.notify_one();
         }
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+    }
+    /// 从队列中取走了一个任务
+    fn taken(&self) {
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        self.release();
+    }
+    /// 放进队列之后就不会再返回错误，任务一定会被执行
+    fn push(self: &Arc<Self>, job: Job) -> Result<(), PoolError> {
+        let mut state = lock(&self.state);
+        if self.stopping.load(Ordering::SeqCst) {
+            return Err(PoolError::ShutDown);
+        }
+        // 先启动 worker 再放任务，新的 worker 要等这里放开锁才能取任务
+        let grew = state.jobs.len() >= self.idle.load(Ordering::SeqCst) && self.grow(&mut state);
+        if self.threads.load(Ordering::SeqCst) == 0 {
+            return Err(PoolError::ShutDown);
+        }
+        state.jobs.push_back(job);
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        if !grew && self.idle.load(Ordering::SeqCst) > 0 {
+            // 没有 worker 在等待时不用通知，`notify_one` 每次都是一次系统调用
+            self.available.notify_one();
+        }
+        Ok(())
+    }
+    /// 和 `push` 一样，放进 injector 之后就不会再返回错误
+    fn inject(self: &Arc<Self>, job: Job) -> Result<(), PoolError> {
+        if self.stopping.load(Ordering::SeqCst) {
+            return Err(PoolError::ShutDown);
+        }
+        let grew = self.threads.load(Ordering::SeqCst) == 0 && self.grow(&mut lock(&self.state));
+        if self.threads.load(Ordering::SeqCst) == 0 {
+            return Err(PoolError::ShutDown);
+        }
+        // 先加计数再放任务，否则 worker 可能先取走任务、把计数减成负数
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        self.injector.push(job);
+        // 和 `next_stolen_job` 中的 fence 配对: 要么这里看到有 worker 在睡眠，
+        // 要么那个 worker 睡眠之前能看到这个任务
+        atomic::fence(Ordering::SeqCst);
+        if self.idle.load(Ordering::SeqCst) > 0 {
+            // 拿一下锁再通知，正在准备睡眠的 worker 拿着锁，这样它不会错过通知
+            let _state = lock(&self.state);
+            self.available.notify_one();
+        } else if !grew {
+            self.grow(&mut lock(&self.state));
+        }
+        Ok(())
+    }
+    /// 还没到 `max_threads` 时启动一个新的 worker，返回是否启动了
+    ///
+    /// 启动失败时任务还是会放进队列，等已有的 worker 来执行；一个 worker 都没有
+    /// (`min_threads` 是 0，并且都空闲退出了)时 `push` 和 `inject` 返回错误
+    fn grow(self: &Arc<Self>, state: &mut State) -> bool {
+        if self.threads.load(Ordering::SeqCst) >= self.config.max_threads {
+            return false;
+        }
+        match spawn(self, state) {
+            Ok(()) => true,
+            Err(e) => {
+                println!("Failed to spawn a worker: {e}");
+                false
             }
         }
     }
+    /// `Scheduler::WorkStealing` 的队列中还有没有任务
+    fn has_stolen_jobs(&self) -> bool {
+        !self.injector.is_empty()
+            || self
+                .stealers
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .values()
+                .any(|stealer| !stealer.is_empty())
+    }
+    /// 任务 panic 时不让它把当前线程也带走，panic 的信息交给 panic hook
+    fn run_job(&self, id: usize, job: Job) {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        let result = panic::catch_unwind(AssertUnwindSafe(job));
+        self.active.fetch_sub(1, Ordering::SeqCst);
+        self.completed.fetch_add(1, Ordering::SeqCst);
+        if let Err(payload) = result {
+            self.panicked.fetch_add(1, Ordering::SeqCst);
+            let message = panic_message(payload);
+            let hook = self
+                .panic_hook
+                .read()
+                .unwrap_or_else(PoisonError::into_inner);
+            match hook.as_ref() {
+                Some(hook) => hook(id, &message),
+                None if self.config.verbose => println!("Worker {} recovered from a panic", id),
+                None => {}
+            }
+        }
+    }
+    #[allow(dead_code)]
+    fn metrics(&self) -> Metrics {
+        Metrics {
+            threads: self.threads.load(Ordering::SeqCst),
+            active: self.active.load(Ordering::SeqCst),
+            idle: self.idle.load(Ordering::SeqCst),
+            queued: self.queued.load(Ordering::SeqCst),
+            completed: self.completed.load(Ordering::SeqCst),
+            panicked: self.panicked.load(Ordering::SeqCst),
+            rejected: self.rejected.load(Ordering::SeqCst),
+        }
+    }
+}
+/// 启动一个新的 worker 线程，调用方要拿着 `state` 的锁，这样新线程要退出时
+/// 一定能在 `handles` 中找到自己的 `JoinHandle`
+fn spawn(shared: &Arc<Shared>, state: &mut State) -> io::Result<()> {
+    let id = state.next_id;
+    let sentinel = Sentinel {
+        id,
+        shared: Arc::clone(shared),
+    };
+    let thread = thread::Builder::new()
+        .name(format!("{}-{}", shared.config.thread_name, id))
+        .spawn(move || {
+            run(id, &sentinel.shared);
+            // 正常退出，不需要重新启动
+            std::mem::forget(sentinel);
+        })?;
+    state.next_id += 1;
+    shared.threads.fetch_add(1, Ordering::SeqCst);
+    state.handles.insert(id, thread);
+    Ok(())
 }
-struct Worker {
+/// worker 线程因为 panic 退出时，在 drop 中启动一个新的线程代替它
+struct Sentinel {
     id: usize,
-    thread: Option<JoinHandle<()>>,
-}
-impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<Receiver<Message>>>) -> Self {
-        // 这里的循环不能用while let(还包括 if let 和 match)
-        /*
-                let thread = thread::spawn(move || {
-                    while let Ok(msg) = receiver.lock().unwrap().recv() {
-                        match msg {
-                            Message::NewJob(job) => {
-                                println!("Worker {} got a job; executing...", id);
-                                job();
-                            },
-                            Message::Terminate => {
-                                println!("Worker {} was told to terminate...", id);
-                                break;
-                            }
-                        }
-                    } //锁直到这里才被释放
-                });
-        */
-        let thread = thread::spawn(move || {
-            loop {
-                let msg = receiver.lock().unwrap().recv().unwrap();
-                /* //这样写也不行
-                let receiver = receiver.lock().expect("failed to get a lock...");
-                let msg = receiver.recv().expect("failed to recv a msg...");
-                */
-                match msg {
-                    Message::NewJob(job) => {
-                        println!("Worker {} got a job; executing...", id);
-                        job();
-                    }
-                    Message::Terminate => {
-                        println!("Worker {} was told to terminate...", id);
-                        break;
+    shared: Arc<Shared>,
+}
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+        retire(self.id, &self.shared);
+        let mut state = lock(&self.shared.state);
+        self.shared.threads.fetch_sub(1, Ordering::SeqCst);
+        state.handles.remove(&self.id);
+        // 关闭时还要有 worker 来执行剩下的任务
+        if self.shared.stopping.load(Ordering::SeqCst)
+            && state.jobs.is_empty()
+            && !self.shared.has_stolen_jobs()
+        {
+            return;
+        }
+        if self.shared.config.verbose {
+            println!("Worker {} died, respawning...", self.id);
+        }
+        if let Err(e) = spawn(&self.shared, &mut state) {
+            println!("Failed to respawn worker {}: {e}", self.id);
+        }
+    }
+}
+fn run(id: usize, shared: &Shared) {
+    let verbose = shared.config.verbose;
+    // `Scheduler::WorkStealing` 时每个 worker 自己的队列
+    let local = match shared.config.scheduler {
+        Scheduler::Shared => None,
+        Scheduler::WorkStealing => {
+            let local = Worker::new_fifo();
+            shared
+                .stealers
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(id, local.stealer());
+            Some(local)
+        }
+    };
+    loop {
+        let job = match &local {
+            None => next_job(id, shared),
+            Some(local) => next_stolen_job(id, shared, local),
+        };
+        let job = match job {
+            Some(job) => job,
+            None => break,
+        };
+        shared.taken();
+        if verbose {
+            println!("Worker {} got a job; executing...", id);
+        }
+        shared.run_job(id, job);
+    }
+    retire(id, shared);
+}
+/// 等待下一个任务，返回 `None` 时 worker 应该退出
+fn next_job(id: usize, shared: &Shared) -> Option<Job> {
+    let config = &shared.config;
+    let mut state = lock(&shared.state);
+    loop {
+        // 关闭时也要先执行完队列中的任务
+        if let Some(job) = state.jobs.pop_front() {
+            return Some(job);
+        }
+        if shared.stopping.load(Ordering::SeqCst) {
+            terminate(id, shared);
+            return None;
+        }
+        shared.idle.fetch_add(1, Ordering::SeqCst);
+        let (guard, wait) = shared
+            .available
+            .wait_timeout(state, config.keep_alive)
+            .unwrap_or_else(PoisonError::into_inner);
+        state = guard;
+        shared.idle.fetch_sub(1, Ordering::SeqCst);
+        if wait.timed_out() && state.jobs.is_empty() && can_retire(shared) {
+            retire_idle(id, shared, &mut state);
+            return None;
+        }
+    }
+}
+/// `Scheduler::WorkStealing` 的 `next_job`
+///
+/// 有任务的时候不用加锁，找不到任务时才拿着 `state` 的锁睡眠
+fn next_stolen_job(id: usize, shared: &Shared, local: &Worker<Job>) -> Option<Job> {
+    let config = &shared.config;
+    loop {
+        // 睡眠和唤醒都要系统调用，先让出几次 CPU 看看有没有新任务，
+        // 任务一个接一个来的时候就不用每次都睡眠了
+        for _ in 0..SPINS {
+            if let Some(job) = find_job(shared, local) {
+                return Some(job);
+            }
+            thread::yield_now();
+        }
+        let state = lock(&shared.state);
+        if shared.stopping.load(Ordering::SeqCst) {
+            terminate(id, shared);
+            return None;
+        }
+        shared.idle.fetch_add(1, Ordering::SeqCst);
+        // 和 `Shared::inject` 中的 fence 配对
+        atomic::fence(Ordering::SeqCst);
+        if shared.has_stolen_jobs() {
+            shared.idle.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        }
+        let (mut state, wait) = shared
+            .available
+            .wait_timeout(state, config.keep_alive)
+            .unwrap_or_else(PoisonError::into_inner);
+        shared.idle.fetch_sub(1, Ordering::SeqCst);
+        if wait.timed_out() && !shared.has_stolen_jobs() && can_retire(shared) {
+            retire_idle(id, shared, &mut state);
+            return None;
+        }
+    }
+}
+/// 依次从自己的队列、injector、别的 worker 的队列中找任务
+fn find_job(shared: &Shared, local: &Worker<Job>) -> Option<Job> {
+    local.pop().or_else(|| {
+        // `Steal::Retry` 表示和别的线程冲突了，要再试一次
+        std::iter::repeat_with(|| {
+            shared.injector.steal_batch_and_pop(local).or_else(|| {
+                shared
+                    .stealers
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .values()
+                    .map(Stealer::steal)
+                    .collect()
+            })
+        })
+        .find(|steal| !steal.is_retry())
+        .and_then(Steal::success)
+    })
+}
+fn steal(injector: &Injector<Job>) -> Option<Job> {
+    loop {
+        match injector.steal() {
+            Steal::Success(job) => return Some(job),
+            Steal::Empty => return None,
+            Steal::Retry => continue,
+        }
+    }
+}
+/// 删掉 worker 的 `Stealer`，它的队列中剩下的任务放回 injector
+fn retire(id: usize, shared: &Shared) {
+    let stealer = shared
+        .stealers
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&id);
+    if let Some(stealer) = stealer {
+        loop {
+            match stealer.steal() {
+                Steal::Success(job) => shared.injector.push(job),
+                Steal::Empty => break,
+                Steal::Retry => continue,
+            }
+        }
+    }
+}
+fn can_retire(shared: &Shared) -> bool {
+    shared.threads.load(Ordering::SeqCst) > shared.config.min_threads
+}
+/// 关闭时退出，要拿着 `state` 的锁调用
+fn terminate(id: usize, shared: &Shared) {
+    if shared.config.verbose {
+        println!("Worker {} was told to terminate...", id);
+    }
+    shared.threads.fetch_sub(1, Ordering::SeqCst);
+}
+/// 空闲超时退出
+fn retire_idle(id: usize, shared: &Shared, state: &mut State) {
+    let config = &shared.config;
+    if config.verbose {
+        println!(
+            "Worker {} has been idle for {:?}, exiting...",
+            id, config.keep_alive
+        );
+    }
+    shared.threads.fetch_sub(1, Ordering::SeqCst);
+    // 自己退出，不需要别人等待
+    state.handles.remove(&id);
+}
+/// 不管锁有没有被 poison 都拿到它
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+/// `panic!` 的参数一般是 `&str` 或者 `String`
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => String::from("Box<dyn Any>"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn submit_and_join() {
+        let pool = ThreadPool::quiet(2);
+        let handles: Vec<_> = (0..8)
+            .map(|i| pool.submit(move || i * i).unwrap())
+            .collect();
+        let squares: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(vec![0, 1, 4, 9, 16, 25, 36, 49], squares);
+
+        let (go, wait) = mpsc::channel::<()>();
+        let handle = pool.submit(move || wait.recv().map(|_| "done")).unwrap();
+        assert_eq!(Ok(None), handle.try_join());
+        assert_eq!(Ok(None), handle.join_timeout(Duration::from_millis(20)));
+        go.send(()).unwrap();
+        assert_eq!(
+            Ok(Some(Ok("done"))),
+            handle.join_timeout(Duration::from_secs(5))
+        );
+        // 结果只能取一次(任务的 sender 可能还没被 drop，所以要等一下)
+        assert_eq!(
+            Err(JoinError::Lost),
+            handle.join_timeout(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn execute_after_shutdown() {
+        let mut pool = ThreadPool::quiet(1);
+        let handle = pool.submit(|| "queued before shutdown").unwrap();
+        pool.shutdown();
+        // 关闭之前已经在队列中的任务还是会执行完
+        assert_eq!(Ok("queued before shutdown"), handle.join());
+        assert_eq!(Err(PoolError::ShutDown), pool.execute(|| {}));
+        assert!(pool.submit(|| 1).is_err());
+    }
+
+    #[test]
+    fn survive_panics() {
+        let pool = ThreadPool::quiet(1);
+        let (sender, receiver) = mpsc::channel();
+        pool.set_panic_hook(move |id, message| {
+            sender.send((id, message.to_string())).unwrap();
+        });
+        pool.execute(|| panic!("boom")).unwrap();
+        assert_eq!((0, String::from("boom")), receiver.recv().unwrap());
+
+        let handle = pool.submit(|| -> i32 { panic!("bad job {}", 42) }).unwrap();
+        assert_eq!(
+            Err(JoinError::Panicked(String::from("bad job 42"))),
+            handle.join()
+        );
+        // 只有一个 worker，它还活着才能执行后面的任务
+        assert_eq!(Ok(2), pool.submit(|| 1 + 1).unwrap().join());
+    }
+
+    #[test]
+    fn respawn_dead_workers() {
+        for scheduler in [Scheduler::Shared, Scheduler::WorkStealing] {
+            let mut pool = ThreadPool::fixed(1).scheduler(scheduler).build();
+            // hook 自己 panic 时 worker 线程会退出，然后被重新启动
+            pool.set_panic_hook(|_, message| panic!("hook failed on '{message}'"));
+            pool.execute(|| panic!("boom")).unwrap();
+            assert_eq!(
+                Ok("still working"),
+                pool.submit(|| "still working").unwrap().join()
+            );
+            // 重新启动过的 worker 也能正常关闭
+            pool.shutdown();
+            assert_eq!(0, pool.metrics().threads);
+            assert_eq!(1, pool.metrics().panicked);
+        }
+    }
+
+    /// 等到 `metrics` 满足条件，最多等 5 秒
+    fn wait_for(pool: &ThreadPool, ok: impl Fn(&Metrics) -> bool) -> Metrics {
+        for _ in 0..500 {
+            let metrics = pool.metrics();
+            if ok(&metrics) {
+                return metrics;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("timed out: {:?}", pool.metrics());
+    }
+
+    #[test]
+    fn grow_and_shrink() {
+        for scheduler in [Scheduler::Shared, Scheduler::WorkStealing] {
+            let pool = ThreadPool::builder()
+                .min_threads(1)
+                .max_threads(3)
+                .keep_alive(Duration::from_millis(50))
+                .thread_name("test")
+                .scheduler(scheduler)
+                .build();
+            assert_eq!(1, pool.metrics().threads);
+
+            let (go, wait) = mpsc::channel::<()>();
+            let wait = Arc::new(Mutex::new(wait));
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    let wait = Arc::clone(&wait);
+                    pool.submit(move || {
+                        wait.lock().unwrap().recv().unwrap();
+                        thread::current().name().unwrap().to_string()
+                    })
+                    .unwrap()
+                })
+                .collect();
+            // 只能增加到 3 个，第 4 个任务在队列中等待
+            let metrics = wait_for(&pool, |m| m.idle == 0 && m.active == 3);
+            assert_eq!((3, 1), (metrics.threads, metrics.queued), "{scheduler:?}");
+
+            for _ in 0..4 {
+                go.send(()).unwrap();
+            }
+            for handle in handles {
+                assert!(handle.join().unwrap().starts_with("test-"));
+            }
+            // 空闲超时后退回到 min_threads
+            let metrics = wait_for(&pool, |m| m.threads == 1);
+            assert_eq!(
+                (0, 0, 4),
+                (metrics.active, metrics.queued, metrics.completed),
+                "{scheduler:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn work_stealing() {
+        let mut pool = ThreadPool::builder()
+            .min_threads(2)
+            .max_threads(4)
+            .scheduler(Scheduler::WorkStealing)
+            .build();
+        let pool_ref = &pool;
+        let counter = Arc::new(AtomicUsize::new(0));
+        // 同时从几个线程提交大量很小的任务
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                let counter = Arc::clone(&counter);
+                scope.spawn(move || {
+                    for _ in 0..10_000 {
+                        let counter = Arc::clone(&counter);
+                        pool_ref
+                            .execute(move || {
+                                counter.fetch_add(1, Ordering::Relaxed);
+                            })
+                            .unwrap();
                     }
-                }
+                });
             }
         });
+        let squares: Vec<_> = (0..100)
+            .map(|i| pool.submit(move || i * i).unwrap())
+            .collect();
+        assert_eq!(
+            (0..100).map(|i| i * i).sum::<i32>(),
+            squares.into_iter().map(|h| h.join().unwrap()).sum::<i32>()
+        );
+        pool.shutdown();
+        assert_eq!(40_000, counter.load(Ordering::Relaxed));
+        let metrics = pool.metrics();
+        assert_eq!(
+            (0, 0, 40_100),
+            (metrics.threads, metrics.queued, metrics.completed)
+        );
+    }
+
+    #[test]
+    fn bounded_queue() {
+        for scheduler in [Scheduler::Shared, Scheduler::WorkStealing] {
+            // 一个 worker 在忙，队列中已经有一个任务
+            let busy_pool = |rejection| {
+                let pool = ThreadPool::fixed(1)
+                    .scheduler(scheduler)
+                    .queue_capacity(1)
+                    .rejection(rejection)
+                    .build();
+                let (go, wait) = mpsc::channel::<()>();
+                pool.execute(move || wait.recv().unwrap()).unwrap();
+                wait_for(&pool, |m| m.active == 1);
+                let queued = pool.submit(|| "queued").unwrap();
+                (pool, go, queued)
+            };
+
+            let (pool, go, queued) = busy_pool(Rejection::Reject);
+            assert_eq!(Err(PoolError::Full), pool.execute(|| {}));
+            assert_eq!(1, pool.metrics().rejected);
+            go.send(()).unwrap();
+            assert_eq!(Ok("queued"), queued.join());
+
+            let (pool, go, queued) = busy_pool(Rejection::DropOldest);
+            let newer = pool.submit(|| "newer").unwrap();
+            go.send(()).unwrap();
+            assert_eq!(Err(JoinError::Lost), queued.join());
+            assert_eq!(Ok("newer"), newer.join());
+            assert_eq!(1, pool.metrics().rejected);
+
+            let (pool, go, queued) = busy_pool(Rejection::CallerRuns);
+            let caller = thread::current().id();
+            let ran_on = pool.submit(|| thread::current().id()).unwrap();
+            // 已经在当前线程中执行完了
+            assert_eq!(Ok(Some(caller)), ran_on.try_join(), "{scheduler:?}");
+            // panic 不会传给调用方，和 worker 中的任务一样交给 panic hook
+            let (sender, receiver) = mpsc::channel();
+            pool.set_panic_hook(move |id, message| {
+                sender.send((id, message.to_string())).unwrap();
+            });
+            assert_eq!(Ok(()), pool.execute(|| panic!("caller boom")));
+            assert_eq!(
+                Ok((CALLER, String::from("caller boom"))),
+                receiver.try_recv()
+            );
+            assert_eq!(1, pool.metrics().panicked);
+            go.send(()).unwrap();
+            assert_eq!(Ok("queued"), queued.join());
+
+            let (pool, go, queued) = busy_pool(Rejection::Block);
+            thread::scope(|scope| {
+                let blocked = scope.spawn(|| pool.submit(|| "unblocked").unwrap().join());
+                thread::sleep(Duration::from_millis(50));
+                assert!(!blocked.is_finished(), "{scheduler:?}");
+                go.send(()).unwrap();
+                assert_eq!(Ok("unblocked"), blocked.join().unwrap());
+            });
+            assert_eq!(Ok("queued"), queued.join());
+            assert_eq!(0, pool.metrics().rejected);
+        }
+    }
 
-        // 每个 `Worker` 都拥有自己的唯一 id
-        Worker {
-            id,
-            thread: Some(thread),
+    #[test]
+    fn every_rejection_after_shutdown() {
+        for scheduler in [Scheduler::Shared, Scheduler::WorkStealing] {
+            for rejection in [
+                Rejection::Block,
+                Rejection::Reject,
+                Rejection::DropOldest,
+                Rejection::CallerRuns,
+            ] {
+                let mut pool = ThreadPool::fixed(1)
+                    .scheduler(scheduler)
+                    .queue_capacity(1)
+                    .rejection(rejection)
+                    .build();
+                pool.shutdown();
+                let ran = Arc::new(AtomicBool::new(false));
+                let job = {
+                    let ran = Arc::clone(&ran);
+                    move || ran.store(true, Ordering::SeqCst)
+                };
+                let case = format!("{scheduler:?} {rejection:?}");
+                assert_eq!(Err(PoolError::ShutDown), pool.execute(job), "{case}");
+                assert!(!ran.load(Ordering::SeqCst), "{case}");
+                assert_eq!(0, pool.metrics().rejected, "{case}");
+            }
+        }
+    }
+
+    #[test]
+    fn drop_oldest_from_worker_queues() {
+        let pool = ThreadPool::fixed(1)
+            .scheduler(Scheduler::WorkStealing)
+            .queue_capacity(3)
+            .rejection(Rejection::DropOldest)
+            .build();
+        let (go, wait) = mpsc::channel::<()>();
+        let wait = Arc::new(Mutex::new(wait));
+        let blocker = || {
+            let wait = Arc::clone(&wait);
+            move || wait.lock().unwrap().recv().unwrap()
+        };
+        pool.execute(blocker()).unwrap();
+        wait_for(&pool, |m| m.active == 1);
+        pool.execute(blocker()).unwrap();
+        let c = pool.submit(|| "c").unwrap();
+        let d = pool.submit(|| "d").unwrap();
+        // worker 从 injector 中拿走一半: 执行第二个 blocker，c 放进自己的队列，d 还在 injector 中
+        go.send(()).unwrap();
+        wait_for(&pool, |m| m.completed == 1 && m.active == 1);
+        let e = pool.submit(|| "e").unwrap();
+        // 队列满了，挤掉的是 worker 的队列中的 c，而不是 injector 中的 d
+        let f = pool.submit(|| "f").unwrap();
+        go.send(()).unwrap();
+        assert_eq!(Err(JoinError::Lost), c.join());
+        let rest: Vec<_> = [d, e, f].into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(vec!["d", "e", "f"], rest);
+        assert_eq!(1, pool.metrics().rejected);
+    }
+
+    #[test]
+    fn bounded_queue_with_many_producers() {
+        const CAPACITY: usize = 2;
+        const JOBS: usize = 500;
+        for scheduler in [Scheduler::Shared, Scheduler::WorkStealing] {
+            for rejection in [
+                Rejection::Block,
+                Rejection::Reject,
+                Rejection::DropOldest,
+                Rejection::CallerRuns,
+            ] {
+                let mut pool = ThreadPool::builder()
+                    .min_threads(1)
+                    .max_threads(2)
+                    .scheduler(scheduler)
+                    .queue_capacity(CAPACITY)
+                    .rejection(rejection)
+                    .build();
+                let monitor = pool.monitor();
+                let executed = Arc::new(AtomicUsize::new(0));
+                let max_queued = AtomicUsize::new(0);
+                thread::scope(|scope| {
+                    for _ in 0..4 {
+                        scope.spawn(|| {
+                            for _ in 0..JOBS {
+                                let executed = Arc::clone(&executed);
+                                let _ = pool.execute(move || {
+                                    thread::sleep(Duration::from_micros(50));
+                                    executed.fetch_add(1, Ordering::SeqCst);
+                                });
+                                max_queued.fetch_max(monitor.metrics().queued, Ordering::SeqCst);
+                            }
+                        });
+                    }
+                });
+                pool.shutdown();
+                let case = format!("{scheduler:?} {rejection:?}");
+                let metrics = pool.metrics();
+                assert!(max_queued.into_inner() <= CAPACITY, "{case}");
+                assert_eq!(0, metrics.queued, "{case}");
+                assert_eq!(0, pool.shared.reserved.load(Ordering::SeqCst), "{case}");
+                // 每个任务要么执行了，要么被拒绝或者被挤掉了
+                let executed = executed.load(Ordering::SeqCst);
+                assert_eq!(executed, metrics.completed, "{case}");
+                assert_eq!(4 * JOBS, executed + metrics.rejected, "{case}");
+                if matches!(rejection, Rejection::Block | Rejection::CallerRuns) {
+                    assert_eq!(0, metrics.rejected, "{case}");
+                }
+            }
         }
     }
 }